@@ -3,6 +3,17 @@ pub use sea_orm_migration::prelude::*;
 mod m20240302_000001_create_user_table;
 mod m20240302_000002_create_user_to_user_chat_table;
 mod m20240203_191931_message;
+mod m20240406_000001_create_session_table;
+mod m20240413_000001_add_chat_name_and_direct_key;
+mod m20240420_000001_add_chat_metadata;
+mod m20240427_000001_add_member_roles_and_system_messages;
+mod m20240504_000001_add_message_edited_and_deleted_at;
+mod m20240511_000001_add_last_read_message_id;
+mod m20240518_000001_add_message_keyset_index;
+mod m20240525_000001_add_message_search;
+mod m20240601_000001_add_message_replies;
+mod m20240608_000001_create_message_reaction_table;
+mod m20240615_000001_create_attachment_table;
 
 pub struct Migrator;
 
@@ -13,6 +24,17 @@ impl MigratorTrait for Migrator {
             Box::new(m20240302_000001_create_user_table::Migration),
             Box::new(m20240302_000002_create_user_to_user_chat_table::Migration),
             Box::new(m20240203_191931_message::Migration),
+            Box::new(m20240406_000001_create_session_table::Migration),
+            Box::new(m20240413_000001_add_chat_name_and_direct_key::Migration),
+            Box::new(m20240420_000001_add_chat_metadata::Migration),
+            Box::new(m20240427_000001_add_member_roles_and_system_messages::Migration),
+            Box::new(m20240504_000001_add_message_edited_and_deleted_at::Migration),
+            Box::new(m20240511_000001_add_last_read_message_id::Migration),
+            Box::new(m20240518_000001_add_message_keyset_index::Migration),
+            Box::new(m20240525_000001_add_message_search::Migration),
+            Box::new(m20240601_000001_add_message_replies::Migration),
+            Box::new(m20240608_000001_create_message_reaction_table::Migration),
+            Box::new(m20240615_000001_create_attachment_table::Migration),
         ]
     }
 }
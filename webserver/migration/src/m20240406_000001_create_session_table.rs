@@ -0,0 +1,50 @@
+use super::m20240302_000001_create_user_table::User;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Session::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Session::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Session::UserId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("user_id_to_session_fk")
+                            .from(Session::Table, Session::UserId)
+                            .to(User::Table, User::Id),
+                    )
+                    .col(ColumnDef::new(Session::CreatedAt).integer().not_null())
+                    .col(ColumnDef::new(Session::ExpiresAt).integer().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Session::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    Id,
+    UserId,
+    CreatedAt,
+    ExpiresAt,
+}
@@ -0,0 +1,51 @@
+use super::m20240302_000001_create_user_table::User;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Chat::Table)
+                    .add_column(ColumnDef::new(Chat::Topic).string().null())
+                    .add_column(ColumnDef::new(Chat::AvatarUrl).string().null())
+                    .add_column(ColumnDef::new(Chat::OwnerId).integer().null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("owner_id_to_chat_fk")
+                            .from_tbl(Chat::Table)
+                            .from_col(Chat::OwnerId)
+                            .to_tbl(User::Table)
+                            .to_col(User::Id),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Chat::Table)
+                    .drop_foreign_key(Alias::new("owner_id_to_chat_fk"))
+                    .drop_column(Chat::Topic)
+                    .drop_column(Chat::AvatarUrl)
+                    .drop_column(Chat::OwnerId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Chat {
+    Table,
+    Topic,
+    AvatarUrl,
+    OwnerId,
+}
@@ -0,0 +1,79 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserInChat::Table)
+                    .add_column(
+                        ColumnDef::new(UserInChat::Role)
+                            .string()
+                            .not_null()
+                            .default("member"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Group owners so far were only recorded on the chat itself.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                r#"UPDATE "user_in_chat" SET "role" = 'owner'
+                FROM "chat"
+                WHERE "chat"."id" = "user_in_chat"."chat_id"
+                AND "chat"."owner_id" = "user_in_chat"."user_id""#,
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .add_column(
+                        ColumnDef::new(Message::IsSystem)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(Message::IsSystem)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserInChat::Table)
+                    .drop_column(UserInChat::Role)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserInChat {
+    Table,
+    Role,
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    IsSystem,
+}
@@ -0,0 +1,74 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEX_NAME: &str = "idx_message_thread_root_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .add_column(ColumnDef::new(Message::ReplyToId).integer().null())
+                    .add_column(ColumnDef::new(Message::ThreadRootId).integer().null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("reply_to_id_to_message_fk")
+                            .from_tbl(Message::Table)
+                            .from_col(Message::ReplyToId)
+                            .to_tbl(Message::Table)
+                            .to_col(Message::Id),
+                    )
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("thread_root_id_to_message_fk")
+                            .from_tbl(Message::Table)
+                            .from_col(Message::ThreadRootId)
+                            .to_tbl(Message::Table)
+                            .to_col(Message::Id),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // The thread panel loads every reply to a root in order.
+        manager
+            .create_index(
+                Index::create()
+                    .name(INDEX_NAME)
+                    .table(Message::Table)
+                    .col(Message::ThreadRootId)
+                    .col(Message::Timestamp)
+                    .col(Message::Id)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name(INDEX_NAME).table(Message::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(Message::ThreadRootId)
+                    .drop_column(Message::ReplyToId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    Id,
+    Timestamp,
+    ReplyToId,
+    ThreadRootId,
+}
@@ -0,0 +1,78 @@
+use super::m20240302_000001_create_user_table::User;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEX_NAME: &str = "idx_message_reaction_message_id_user_id_emoji";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MessageReaction::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MessageReaction::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(MessageReaction::MessageId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("message_id_to_message_reaction_fk")
+                            .from(MessageReaction::Table, MessageReaction::MessageId)
+                            .to(Message::Table, Message::Id),
+                    )
+                    .col(ColumnDef::new(MessageReaction::UserId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("user_id_to_message_reaction_fk")
+                            .from(MessageReaction::Table, MessageReaction::UserId)
+                            .to(User::Table, User::Id),
+                    )
+                    .col(ColumnDef::new(MessageReaction::Emoji).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // A user reacts with each emoji at most once, it also serves loading a page's reactions.
+        manager
+            .create_index(
+                Index::create()
+                    .name(INDEX_NAME)
+                    .table(MessageReaction::Table)
+                    .col(MessageReaction::MessageId)
+                    .col(MessageReaction::UserId)
+                    .col(MessageReaction::Emoji)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MessageReaction::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MessageReaction {
+    Table,
+    Id,
+    MessageId,
+    UserId,
+    Emoji,
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    Id,
+}
@@ -0,0 +1,77 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEX_NAME: &str = "idx_attachment_message_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Attachment::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Attachment::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Attachment::MessageId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("message_id_to_attachment_fk")
+                            .from(Attachment::Table, Attachment::MessageId)
+                            .to(Message::Table, Message::Id),
+                    )
+                    .col(ColumnDef::new(Attachment::Filename).string().not_null())
+                    .col(ColumnDef::new(Attachment::ContentType).string().not_null())
+                    .col(ColumnDef::new(Attachment::Size).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(Attachment::StorageKey)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Attachments are loaded for a page of messages at a time.
+        manager
+            .create_index(
+                Index::create()
+                    .name(INDEX_NAME)
+                    .table(Attachment::Table)
+                    .col(Attachment::MessageId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Attachment::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Attachment {
+    Table,
+    Id,
+    MessageId,
+    Filename,
+    ContentType,
+    Size,
+    StorageKey,
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    Id,
+}
@@ -0,0 +1,24 @@
+use tracing::info;
+
+use crate::chat::broker::Topic;
+use crate::chat::database::{ChatDatabase, ChatMember};
+use crate::chat::events::ServerEvent;
+use crate::AppState;
+
+/// Tells the sidebars of the given users to refresh the chat's row.
+pub async fn notify_users(state: &AppState, chat_id: i32, user_ids: &[i32]) {
+    let event = ServerEvent::ChatChanged { chat_id };
+    for user_id in user_ids {
+        if let Err(err) = state.broker.publish(Topic::User(*user_id), &event).await {
+            info!("{}", err.to_string());
+        }
+    }
+}
+
+/// Tells the sidebar of every member to refresh the chat's row.
+pub async fn notify_members(state: &AppState, member: &ChatMember) {
+    match ChatDatabase::get_member_ids(&state.db, member).await {
+        Ok(user_ids) => notify_users(state, member.chat_id(), &user_ids).await,
+        Err(err) => info!("{}", err.to_string()),
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::chat::broker::{Broker, Topic};
+use crate::chat::events::ServerEvent;
+use crate::chat::hub::{EventHub, Subscription};
+use crate::chat::presence::PresenceTracker;
+
+/// Keeps everything inside the process, for single node deployments and tests.
+#[derive(Default)]
+pub struct MemoryBroker {
+    hub: Arc<EventHub>,
+    /// Open connections of every online user.
+    connections: Mutex<HashMap<i32, HashSet<String>>>,
+}
+
+#[async_trait]
+impl Broker for MemoryBroker {
+    async fn publish(&self, topic: Topic, event: &ServerEvent) -> anyhow::Result<()> {
+        self.hub.dispatch(topic, event.clone());
+        Ok(())
+    }
+
+    async fn subscribe(&self, topic: Topic) -> anyhow::Result<Subscription> {
+        Ok(self.hub.subscribe(topic))
+    }
+}
+
+#[async_trait]
+impl PresenceTracker for MemoryBroker {
+    async fn connect(&self, user_id: i32, connection_id: &str) -> anyhow::Result<bool> {
+        let mut connections = self.connections.lock().unwrap();
+        let user_connections = connections.entry(user_id).or_default();
+        user_connections.insert(connection_id.to_owned());
+        Ok(user_connections.len() == 1)
+    }
+
+    // Connections can't outlive the process, so there is nothing to keep alive.
+    async fn heartbeat(&self, _user_id: i32, _connection_id: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn disconnect(&self, user_id: i32, connection_id: &str) -> anyhow::Result<bool> {
+        let mut connections = self.connections.lock().unwrap();
+        let Some(user_connections) = connections.get_mut(&user_id) else {
+            return Ok(false);
+        };
+        user_connections.remove(connection_id);
+        if !user_connections.is_empty() {
+            return Ok(false);
+        }
+        connections.remove(&user_id);
+        Ok(true)
+    }
+
+    async fn online_users(&self, user_ids: &[i32]) -> anyhow::Result<HashSet<i32>> {
+        let connections = self.connections.lock().unwrap();
+        Ok(user_ids
+            .iter()
+            .filter(|user_id| connections.contains_key(user_id))
+            .copied()
+            .collect())
+    }
+}
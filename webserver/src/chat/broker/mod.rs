@@ -0,0 +1,66 @@
+mod memory;
+mod redis;
+mod redis_streams;
+
+pub use memory::MemoryBroker;
+pub use redis::{RedisBroker, RedisPresence};
+pub use redis_streams::RedisStreamBroker;
+
+use std::fmt;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+
+use crate::chat::events::ServerEvent;
+use crate::chat::hub::Subscription;
+
+/// Where events are published, named `chat:{id}` and `user:{id}` in Redis.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    /// Every socket that has the chat open.
+    Chat(i32),
+    /// Every sidebar of the user.
+    User(i32),
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topic::Chat(chat_id) => write!(f, "chat:{chat_id}"),
+            Topic::User(user_id) => write!(f, "user:{user_id}"),
+        }
+    }
+}
+
+impl FromStr for Topic {
+    type Err = ();
+
+    fn from_str(topic: &str) -> Result<Self, Self::Err> {
+        let (kind, id) = topic.split_once(':').ok_or(())?;
+        let id = id.parse().map_err(|_| ())?;
+        match kind {
+            "chat" => Ok(Topic::Chat(id)),
+            "user" => Ok(Topic::User(id)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Carries events between the processes serving the app.
+#[async_trait]
+pub trait Broker: Send + Sync {
+    /// Publishes an event to every socket subscribed to the topic.
+    async fn publish(&self, topic: Topic, event: &ServerEvent) -> anyhow::Result<()>;
+
+    async fn subscribe(&self, topic: Topic) -> anyhow::Result<Subscription>;
+
+    /// The events a client missed, from after the log position `after` up to
+    /// where `subscription` started. Only brokers that keep a log have any.
+    async fn replay(
+        &self,
+        _subscription: &Subscription,
+        _after: &str,
+    ) -> anyhow::Result<Vec<ServerEvent>> {
+        Ok(vec![])
+    }
+}
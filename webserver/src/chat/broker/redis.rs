@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use tracing::info;
+
+use crate::chat::broker::{Broker, Topic};
+use crate::chat::events::ServerEvent;
+use crate::chat::hub::{EventHub, Subscription};
+use crate::chat::presence::{PresenceTracker, PRESENCE_TTL};
+
+/// Shares events between `webserver` processes through Redis pub/sub channels
+/// named after their [`Topic`].
+pub struct RedisBroker {
+    /// Shared by everything that publishes or runs plain commands.
+    conn: ConnectionManager,
+    hub: Arc<EventHub>,
+}
+
+impl RedisBroker {
+    /// Connects and starts forwarding the channels to this process' sockets.
+    pub async fn connect(url: &str) -> redis::RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = client.get_connection_manager().await?;
+        let hub = Arc::new(EventHub::default());
+        tokio::spawn(forward(client, hub.clone()));
+        Ok(Self { conn, hub })
+    }
+
+    pub fn presence(&self) -> RedisPresence {
+        RedisPresence::new(self.conn.clone())
+    }
+}
+
+/// Forwards every event published in Redis, reconnecting if the
+/// subscription drops.
+async fn forward(client: redis::Client, hub: Arc<EventHub>) {
+    loop {
+        if let Err(err) = listen(&client, &hub).await {
+            info!("{}", err.to_string());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Pattern subscriptions cover every topic, however many are open.
+async fn listen(client: &redis::Client, hub: &EventHub) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.psubscribe("chat:*").await?;
+    pubsub.psubscribe("user:*").await?;
+    let mut messages = pubsub.into_on_message();
+
+    while let Some(msg) = messages.next().await {
+        let Ok(topic) = msg.get_channel_name().parse() else {
+            continue;
+        };
+        let payload: String = msg.get_payload()?;
+        match serde_json::from_str(&payload) {
+            Ok(event) => hub.dispatch(topic, event),
+            Err(err) => info!("Ignoring malformed chat event: {}", err.to_string()),
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Broker for RedisBroker {
+    async fn publish(&self, topic: Topic, event: &ServerEvent) -> anyhow::Result<()> {
+        // Clones share the one multiplexed connection.
+        let mut conn = self.conn.clone();
+        conn.publish::<_, _, ()>(topic.to_string(), serde_json::to_string(event)?)
+            .await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self, topic: Topic) -> anyhow::Result<Subscription> {
+        Ok(self.hub.subscribe(topic))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards!")
+        .as_secs()
+}
+
+/// Every user has a sorted set of their open sockets, scored by when each one expires.
+fn presence_key(user_id: i32) -> String {
+    format!("presence:{user_id}")
+}
+
+/// Tracks the sockets of every process in Redis.
+pub struct RedisPresence {
+    conn: ConnectionManager,
+}
+
+impl RedisPresence {
+    pub fn new(conn: ConnectionManager) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait]
+impl PresenceTracker for RedisPresence {
+    async fn connect(&self, user_id: i32, connection_id: &str) -> anyhow::Result<bool> {
+        let key = presence_key(user_id);
+        let now = now();
+        let mut conn = self.conn.clone();
+        let (before, after): (u64, u64) = redis::pipe()
+            .atomic()
+            .zrembyscore(&key, "-inf", now)
+            .ignore()
+            .zcard(&key)
+            .zadd(&key, connection_id, now + PRESENCE_TTL.as_secs())
+            .ignore()
+            .zcard(&key)
+            .expire(&key, PRESENCE_TTL.as_secs() as i64)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(before == 0 && after > 0)
+    }
+
+    async fn heartbeat(&self, user_id: i32, connection_id: &str) -> anyhow::Result<()> {
+        let key = presence_key(user_id);
+        let mut conn = self.conn.clone();
+        redis::pipe()
+            .atomic()
+            .zadd(&key, connection_id, now() + PRESENCE_TTL.as_secs())
+            .ignore()
+            .expire(&key, PRESENCE_TTL.as_secs() as i64)
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn disconnect(&self, user_id: i32, connection_id: &str) -> anyhow::Result<bool> {
+        let key = presence_key(user_id);
+        let mut conn = self.conn.clone();
+        let (remaining,): (u64,) = redis::pipe()
+            .atomic()
+            .zrem(&key, connection_id)
+            .ignore()
+            .zrembyscore(&key, "-inf", now())
+            .ignore()
+            .zcard(&key)
+            .query_async(&mut conn)
+            .await?;
+        Ok(remaining == 0)
+    }
+
+    async fn online_users(&self, user_ids: &[i32]) -> anyhow::Result<HashSet<i32>> {
+        if user_ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let mut conn = self.conn.clone();
+        let now = now();
+        let mut pipe = redis::pipe();
+        for user_id in user_ids {
+            pipe.zcount(presence_key(*user_id), format!("({now}"), "+inf");
+        }
+        let counts: Vec<u64> = pipe.query_async(&mut conn).await?;
+        Ok(user_ids
+            .iter()
+            .zip(counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(user_id, _)| *user_id)
+            .collect())
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::streams::{
+    StreamId, StreamMaxlen, StreamRangeReply, StreamReadOptions, StreamReadReply,
+};
+use redis::AsyncCommands;
+use tracing::info;
+
+use crate::chat::broker::{Broker, RedisPresence, Topic};
+use crate::chat::events::ServerEvent;
+use crate::chat::hub::{EventHub, Subscription};
+
+/// How long a read waits for new entries before picking up newly opened chats.
+const READ_BLOCK: Duration = Duration::from_millis(500);
+const READ_COUNT: usize = 100;
+
+fn stream_key(topic: Topic) -> String {
+    format!("events:{topic}")
+}
+
+/// Keeps the events of every topic in a capped Redis stream. Each process reads
+/// the streams its sockets listen to from where it left off, so nothing is lost
+/// while it reconnects to Redis, and each socket can replay the stream from the
+/// last entry its client saw, even one delivered by another process.
+pub struct RedisStreamBroker {
+    conn: ConnectionManager,
+    hub: Arc<EventHub>,
+    max_len: usize,
+}
+
+impl RedisStreamBroker {
+    /// Connects and starts reading the streams open in this process.
+    pub async fn connect(url: &str, max_len: usize) -> redis::RedisResult<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = client.get_connection_manager().await?;
+        let hub = Arc::new(EventHub::default());
+        tokio::spawn(forward(client, hub.clone()));
+        Ok(Self { conn, hub, max_len })
+    }
+
+    pub fn presence(&self) -> RedisPresence {
+        RedisPresence::new(self.conn.clone())
+    }
+}
+
+/// Forwards stream entries to the hub, reconnecting if reading fails. Reads
+/// block, so they get a connection of their own.
+async fn forward(client: redis::Client, hub: Arc<EventHub>) {
+    loop {
+        if let Err(err) = read(&client, &hub).await {
+            info!("{}", err.to_string());
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn read(client: &redis::Client, hub: &EventHub) -> redis::RedisResult<()> {
+    let mut conn = client.get_async_connection().await?;
+    let options = StreamReadOptions::default()
+        .block(READ_BLOCK.as_millis() as usize)
+        .count(READ_COUNT);
+
+    loop {
+        let (keys, ids): (Vec<String>, Vec<String>) = hub
+            .positions()
+            .into_iter()
+            .map(|(topic, id)| (stream_key(topic), id))
+            .unzip();
+        if keys.is_empty() {
+            tokio::time::sleep(READ_BLOCK).await;
+            continue;
+        }
+
+        // Nil when the block times out without new entries.
+        let reply: Option<StreamReadReply> = conn.xread_options(&keys, &ids, &options).await?;
+        for stream in reply.map(|reply| reply.keys).unwrap_or_default() {
+            let Some(Ok(topic)) = stream.key.strip_prefix("events:").map(str::parse) else {
+                continue;
+            };
+            for entry in stream.ids {
+                match parse_entry(&entry) {
+                    Some(event) => hub.dispatch_at(topic, entry.id, event),
+                    None => info!("Ignoring malformed chat event {}", entry.id),
+                }
+            }
+        }
+    }
+}
+
+fn parse_entry(entry: &StreamId) -> Option<ServerEvent> {
+    let payload = entry.get::<String>("event")?;
+    serde_json::from_str(&payload).ok()
+}
+
+#[async_trait]
+impl Broker for RedisStreamBroker {
+    async fn publish(&self, topic: Topic, event: &ServerEvent) -> anyhow::Result<()> {
+        let mut conn = self.conn.clone();
+        conn.xadd_maxlen::<_, _, _, _, ()>(
+            stream_key(topic),
+            StreamMaxlen::Approx(self.max_len),
+            "*",
+            &[("event", serde_json::to_string(event)?)],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self, topic: Topic) -> anyhow::Result<Subscription> {
+        // Read before joining the hub, it only counts if this opens the topic.
+        // Entries added in between are newer, so they are read rather than lost.
+        let mut conn = self.conn.clone();
+        let newest: StreamRangeReply = conn.xrevrange_count(stream_key(topic), "+", "-", 1).await?;
+        let newest = newest
+            .ids
+            .first()
+            .map_or_else(|| "0-0".to_owned(), |entry| entry.id.clone());
+        Ok(self.hub.subscribe_at(topic, Some(newest)))
+    }
+
+    async fn replay(
+        &self,
+        subscription: &Subscription,
+        after: &str,
+    ) -> anyhow::Result<Vec<ServerEvent>> {
+        let Some(start) = subscription.start() else {
+            return Ok(vec![]);
+        };
+        let mut conn = self.conn.clone();
+        // `(` makes the range exclusive, the client already has `after` itself.
+        let missed: StreamRangeReply = conn
+            .xrange(stream_key(subscription.topic()), format!("({after}"), start)
+            .await?;
+        Ok(missed.ids.iter().filter_map(parse_entry).collect())
+    }
+}
@@ -1,18 +1,265 @@
+use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::entities::{chat, message, user_in_chat};
-use migration::OnConflict;
+use crate::entities::{
+    attachment, chat, message, message_reaction, user, user_in_chat, user_in_chat::ChatRole,
+};
 use sea_orm::*;
+use serde::{Deserialize, Serialize};
 
 pub struct ChatDatabase;
 
+/// Proof that a user belongs to a chat. It can only be obtained through
+/// [`ChatDatabase::authorize`], so any query taking one is membership checked.
+#[derive(Clone, Copy)]
+pub struct ChatMember {
+    chat_id: i32,
+    user_id: i32,
+    role: ChatRole,
+}
+
+impl ChatMember {
+    pub fn chat_id(&self) -> i32 {
+        self.chat_id
+    }
+
+    pub fn user_id(&self) -> i32 {
+        self.user_id
+    }
+
+    pub fn role(&self) -> ChatRole {
+        self.role
+    }
+}
+
+pub struct MemberSummary {
+    pub user_id: i32,
+    pub username: String,
+    pub role: ChatRole,
+    pub last_read_message_id: Option<i32>,
+}
+
+/// A chat together with the name it should be shown under for a given user.
+pub struct ChatSummary {
+    pub chat: chat::Model,
+    /// The group name, or the other participant's username for direct chats.
+    pub display_name: String,
+    /// Messages from others the user hasn't read yet.
+    pub unread: i64,
+    /// The other participant of a direct chat.
+    pub partner_id: Option<i32>,
+    pub last_message: Option<LastMessage>,
+}
+
+/// Preview of the newest message of a chat.
+pub struct LastMessage {
+    pub text: String,
+    pub username: String,
+    pub timestamp: i32,
+    pub is_system: bool,
+    pub deleted: bool,
+}
+
+/// What a reply quotes of the message it answers.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplyPreview {
+    pub message_id: i32,
+    pub username: String,
+    /// Empty once the message is deleted, previews are published with replies.
+    pub text: String,
+    pub deleted: bool,
+}
+
+/// Everyone who reacted to a message with one emoji.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Reaction {
+    pub emoji: String,
+    pub user_ids: Vec<i32>,
+}
+
+/// The reactions of the messages on a page, in the order they were first used.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Reactions(HashMap<i32, Vec<Reaction>>);
+
+impl Reactions {
+    /// Takes a reference like `HashMap::get`, which is what templates pass.
+    pub fn of(&self, message_id: &i32) -> &[Reaction] {
+        self.0.get(message_id).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl From<(i32, Vec<Reaction>)> for Reactions {
+    fn from((message_id, reactions): (i32, Vec<Reaction>)) -> Self {
+        Self(HashMap::from([(message_id, reactions)]))
+    }
+}
+
+/// A file already put in storage, to be attached to a new message.
+pub struct NewAttachment {
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    pub storage_key: String,
+}
+
+/// A message matching a search, with the matching words marked.
+pub struct SearchHit {
+    pub message_id: i32,
+    pub chat_id: i32,
+    pub chat_name: String,
+    pub username: String,
+    pub timestamp: i32,
+    pub snippets: Vec<Snippet>,
+}
+
+impl SearchHit {
+    pub fn relative_time(&self) -> String {
+        relative_time(self.timestamp)
+    }
+}
+
+/// Part of a search hit's excerpt, `matched` if it is one of the searched words.
+pub struct Snippet {
+    pub text: String,
+    pub matched: bool,
+}
+
+/// Marks `ts_headline` puts around matches, so the excerpt can be split on them
+/// and still be escaped. A message containing them only gets odd highlighting.
+const MATCH_START: char = '\u{1}';
+const MATCH_END: char = '\u{2}';
+
+fn snippets(headline: &str) -> Vec<Snippet> {
+    let mut snippets = vec![];
+    for (index, part) in headline.split([MATCH_START, MATCH_END]).enumerate() {
+        if !part.is_empty() {
+            snippets.push(Snippet {
+                text: part.to_owned(),
+                // Every other part sits between a start and an end mark.
+                matched: index % 2 == 1,
+            });
+        }
+    }
+    snippets
+}
+
+impl LastMessage {
+    pub fn relative_time(&self) -> String {
+        relative_time(self.timestamp)
+    }
+}
+
+/// How long ago something happened, e.g. `5m` or `3d`.
+fn relative_time(timestamp: i32) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards!")
+        .as_secs() as i32;
+    let seconds = (now - timestamp).max(0);
+    match seconds {
+        0..=59 => "now".to_owned(),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        86400..=604799 => format!("{}d", seconds / 86400),
+        _ => format!("{}w", seconds / 604800),
+    }
+}
+
 impl ChatDatabase {
+    /// Checks the `user_in_chat` table, returning `None` when the user is not a member.
+    pub async fn authorize(
+        db: &DbConn,
+        chat_id: i32,
+        user_id: i32,
+    ) -> Result<Option<ChatMember>, DbErr> {
+        let membership = user_in_chat::Entity::find()
+            .filter(user_in_chat::Column::ChatId.eq(chat_id))
+            .filter(user_in_chat::Column::UserId.eq(user_id))
+            .one(db)
+            .await?;
+
+        Ok(membership.map(|membership| ChatMember {
+            chat_id: membership.chat_id,
+            user_id: membership.user_id,
+            role: membership.role,
+        }))
+    }
+
+    /// Adds a message, optionally as a reply to another message of the chat. The
+    /// reply joins the thread of that message, or starts one rooted at it. Deleted
+    /// messages can't be replied to.
     pub async fn add_message(
         db: &DbConn,
+        member: &ChatMember,
         text: String,
-        user_id: i32,
-        chat_id: i32,
+        reply_to_id: Option<i32>,
     ) -> Result<message::Model, DbErr> {
+        let reply = match reply_to_id {
+            Some(reply_to_id) => {
+                let parent = message::Entity::find_by_id(reply_to_id)
+                    .filter(message::Column::ChatId.eq(member.chat_id))
+                    .filter(message::Column::IsSystem.eq(false))
+                    .filter(message::Column::DeletedAt.is_null())
+                    .one(db)
+                    .await?
+                    .ok_or(DbErr::RecordNotFound(format!("message {reply_to_id}")))?;
+                Some((parent.id, parent.thread_root_id.unwrap_or(parent.id)))
+            }
+            None => None,
+        };
+        Self::insert_message(db, member, text, false, reply).await
+    }
+
+    /// Adds a notice such as "alice added bob" to the chat history, attributed to `member`.
+    pub async fn add_system_message(
+        db: &DbConn,
+        member: &ChatMember,
+        text: String,
+    ) -> Result<message::Model, DbErr> {
+        Self::insert_message(db, member, text, true, None).await
+    }
+
+    /// Adds a message with a file that was already put in storage. Either both
+    /// are saved or neither is.
+    pub async fn add_attachment_message(
+        db: &DbConn,
+        member: &ChatMember,
+        text: String,
+        upload: NewAttachment,
+    ) -> Result<(message::Model, attachment::Model), DbErr> {
+        let member = *member;
+        db.transaction::<_, (message::Model, attachment::Model), DbErr>(|txn| {
+            Box::pin(async move {
+                let message = Self::insert_message(txn, &member, text, false, None).await?;
+                let attachment = attachment::ActiveModel {
+                    message_id: Set(message.id),
+                    filename: Set(upload.filename),
+                    content_type: Set(upload.content_type),
+                    size: Set(upload.size),
+                    storage_key: Set(upload.storage_key),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+                Ok((message, attachment))
+            })
+        })
+        .await
+        .map_err(|err| match err {
+            TransactionError::Connection(err) | TransactionError::Transaction(err) => err,
+        })
+    }
+
+    async fn insert_message(
+        db: &impl ConnectionTrait,
+        member: &ChatMember,
+        text: String,
+        is_system: bool,
+        reply: Option<(i32, i32)>,
+    ) -> Result<message::Model, DbErr> {
+        let ChatMember {
+            chat_id, user_id, ..
+        } = *member;
         let seconds_since_epoch = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards!")
@@ -23,6 +270,9 @@ impl ChatDatabase {
             user_id: Set(user_id.to_owned()),
             chat_id: Set(chat_id.to_owned()),
             timestamp: Set(seconds_since_epoch.to_owned()),
+            is_system: Set(is_system),
+            reply_to_id: Set(reply.map(|(reply_to_id, _)| reply_to_id)),
+            thread_root_id: Set(reply.map(|(_, thread_root_id)| thread_root_id)),
             ..Default::default()
         }
         .save(db)
@@ -31,57 +281,958 @@ impl ChatDatabase {
         chat::ActiveModel {
             id: ActiveValue::Unchanged(chat_id),
             last_changed_timestamp: Set(seconds_since_epoch),
+            ..Default::default()
         }
         .save(db)
         .await?;
 
-        Ok(message::Model::try_from(model)?)
+        message::Model::try_from(model)
     }
 
-    pub async fn get_chat_messages_by_id(
+    /// Replaces the text of one of the member's own messages. Returns `None` when
+    /// the message doesn't exist, belongs to someone else or was deleted.
+    pub async fn edit_message(
         db: &DbConn,
-        chat_id: i64,
-        page: u64,
-        messages_per_page: u64,
-    ) -> Result<(Vec<message::Model>, u64), DbErr> {
-        let paginator = message::Entity::find()
-            .filter(message::Column::ChatId.eq(chat_id))
+        member: &ChatMember,
+        message_id: i32,
+        text: String,
+    ) -> Result<Option<message::Model>, DbErr> {
+        let Some(message) = Self::get_own_message(db, member, message_id).await? else {
+            return Ok(None);
+        };
+        let seconds_since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards!")
+            .as_secs() as i32;
+
+        let mut message: message::ActiveModel = message.into();
+        message.text = Set(text);
+        message.edited_at = Set(Some(seconds_since_epoch));
+        message.update(db).await.map(Some)
+    }
+
+    /// Soft deletes one of the member's own messages, keeping the row so the
+    /// history stays consistent for everyone else.
+    pub async fn delete_message(
+        db: &DbConn,
+        member: &ChatMember,
+        message_id: i32,
+    ) -> Result<Option<message::Model>, DbErr> {
+        let Some(message) = Self::get_own_message(db, member, message_id).await? else {
+            return Ok(None);
+        };
+        let seconds_since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards!")
+            .as_secs() as i32;
+
+        let mut message: message::ActiveModel = message.into();
+        message.deleted_at = Set(Some(seconds_since_epoch));
+        message.update(db).await.map(Some)
+    }
+
+    async fn get_own_message(
+        db: &DbConn,
+        member: &ChatMember,
+        message_id: i32,
+    ) -> Result<Option<message::Model>, DbErr> {
+        message::Entity::find_by_id(message_id)
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(message::Column::UserId.eq(member.user_id))
+            .filter(message::Column::IsSystem.eq(false))
+            .filter(message::Column::DeletedAt.is_null())
+            .one(db)
+            .await
+    }
+
+    /// The newest `limit` messages older than `before`, the `(timestamp, id)` of the
+    /// oldest message already shown, or the newest overall without it. Returned
+    /// oldest first, together with whether there are even older ones.
+    pub async fn get_chat_messages_before(
+        db: &DbConn,
+        member: &ChatMember,
+        before: Option<(i32, i32)>,
+        limit: u64,
+    ) -> Result<(Vec<message::Model>, bool), DbErr> {
+        let mut query = message::Entity::find().filter(message::Column::ChatId.eq(member.chat_id));
+        if let Some((timestamp, id)) = before {
+            query = query.filter(
+                Condition::any()
+                    .add(message::Column::Timestamp.lt(timestamp))
+                    .add(
+                        Condition::all()
+                            .add(message::Column::Timestamp.eq(timestamp))
+                            .add(message::Column::Id.lt(id)),
+                    ),
+            );
+        }
+        let mut messages = query
+            .order_by_desc(message::Column::Timestamp)
+            .order_by_desc(message::Column::Id)
+            .limit(limit + 1)
+            .all(db)
+            .await?;
+
+        let has_older = messages.len() as u64 > limit;
+        messages.truncate(limit as usize);
+        messages.reverse();
+        Ok((messages, has_older))
+    }
+
+    /// The oldest `limit` messages newer than `after`, the `(timestamp, id)` of the
+    /// newest message already shown. Returned oldest first, together with whether
+    /// there are even newer ones.
+    pub async fn get_chat_messages_newer(
+        db: &DbConn,
+        member: &ChatMember,
+        after: (i32, i32),
+        limit: u64,
+    ) -> Result<(Vec<message::Model>, bool), DbErr> {
+        let (timestamp, id) = after;
+        let mut messages = message::Entity::find()
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(
+                Condition::any()
+                    .add(message::Column::Timestamp.gt(timestamp))
+                    .add(
+                        Condition::all()
+                            .add(message::Column::Timestamp.eq(timestamp))
+                            .add(message::Column::Id.gt(id)),
+                    ),
+            )
             .order_by_asc(message::Column::Timestamp)
-            .paginate(db, messages_per_page);
-        let num_page = paginator.num_pages().await?;
-        paginator.fetch_page(page - 1).await.map(|p| (p, num_page))
+            .order_by_asc(message::Column::Id)
+            .limit(limit + 1)
+            .all(db)
+            .await?;
+
+        let has_newer = messages.len() as u64 > limit;
+        messages.truncate(limit as usize);
+        Ok((messages, has_newer))
+    }
+
+    /// Up to `context` messages on either side of `message_id` and the message
+    /// itself, oldest first, with whether there is more in either direction.
+    /// `None` if the message is not in the member's chat.
+    pub async fn get_message_window(
+        db: &DbConn,
+        member: &ChatMember,
+        message_id: i32,
+        context: u64,
+    ) -> Result<Option<(Vec<message::Model>, bool, bool)>, DbErr> {
+        let Some(target) = message::Entity::find_by_id(message_id)
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .one(db)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let cursor = (target.timestamp, target.id);
+        let (mut messages, has_older) =
+            Self::get_chat_messages_before(db, member, Some(cursor), context).await?;
+        let (newer, has_newer) = Self::get_chat_messages_newer(db, member, cursor, context).await?;
+        messages.push(target);
+        messages.extend(newer);
+        Ok(Some((messages, has_older, has_newer)))
+    }
+
+    /// Previews of the messages the given ones reply to, by id.
+    pub async fn get_reply_previews(
+        db: &DbConn,
+        member: &ChatMember,
+        messages: &[message::Model],
+    ) -> Result<HashMap<i32, ReplyPreview>, DbErr> {
+        let ids: Vec<i32> = messages
+            .iter()
+            .filter_map(|message| message.reply_to_id)
+            .collect();
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let previews = message::Entity::find()
+            .select_only()
+            .column(message::Column::Id)
+            .column(user::Column::Username)
+            .column(message::Column::Text)
+            .column(message::Column::DeletedAt)
+            .join(JoinType::InnerJoin, message::Relation::User.def())
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(message::Column::Id.is_in(ids))
+            .into_tuple::<(i32, String, String, Option<i32>)>()
+            .all(db)
+            .await?;
+
+        Ok(previews
+            .into_iter()
+            .map(|(message_id, username, text, deleted_at)| {
+                let deleted = deleted_at.is_some();
+                let preview = ReplyPreview {
+                    message_id,
+                    username,
+                    text: if deleted { String::new() } else { text },
+                    deleted,
+                };
+                (message_id, preview)
+            })
+            .collect())
+    }
+
+    pub async fn get_reply_preview(
+        db: &DbConn,
+        member: &ChatMember,
+        message: &message::Model,
+    ) -> Result<Option<ReplyPreview>, DbErr> {
+        let Some(reply_to_id) = message.reply_to_id else {
+            return Ok(None);
+        };
+        Ok(
+            Self::get_reply_previews(db, member, std::slice::from_ref(message))
+                .await?
+                .remove(&reply_to_id),
+        )
+    }
+
+    /// Adds the member's reaction to a message, or takes it back if it was there.
+    /// Returns the message's reactions afterwards, `None` if the message can't be
+    /// reacted to.
+    pub async fn toggle_reaction(
+        db: &DbConn,
+        member: &ChatMember,
+        message_id: i32,
+        emoji: String,
+    ) -> Result<Option<Vec<Reaction>>, DbErr> {
+        let message = message::Entity::find_by_id(message_id)
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(message::Column::IsSystem.eq(false))
+            .filter(message::Column::DeletedAt.is_null())
+            .one(db)
+            .await?;
+        if message.is_none() {
+            return Ok(None);
+        }
+
+        let removed = message_reaction::Entity::delete_many()
+            .filter(message_reaction::Column::MessageId.eq(message_id))
+            .filter(message_reaction::Column::UserId.eq(member.user_id))
+            .filter(message_reaction::Column::Emoji.eq(emoji.clone()))
+            .exec(db)
+            .await?;
+        if removed.rows_affected == 0 {
+            // A double click races the first insert, the unique index settles it.
+            message_reaction::Entity::insert(message_reaction::ActiveModel {
+                message_id: Set(message_id),
+                user_id: Set(member.user_id),
+                emoji: Set(emoji),
+                ..Default::default()
+            })
+            .on_conflict(
+                sea_query::OnConflict::columns([
+                    message_reaction::Column::MessageId,
+                    message_reaction::Column::UserId,
+                    message_reaction::Column::Emoji,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(db)
+            .await?;
+        }
+
+        let mut reactions = Self::get_reactions(db, member, &[message_id]).await?;
+        Ok(Some(reactions.0.remove(&message_id).unwrap_or_default()))
+    }
+
+    pub async fn get_reactions(
+        db: &DbConn,
+        member: &ChatMember,
+        message_ids: &[i32],
+    ) -> Result<Reactions, DbErr> {
+        if message_ids.is_empty() {
+            return Ok(Reactions::default());
+        }
+        let rows = message_reaction::Entity::find()
+            .select_only()
+            .column(message_reaction::Column::MessageId)
+            .column(message_reaction::Column::Emoji)
+            .column(message_reaction::Column::UserId)
+            .join(
+                JoinType::InnerJoin,
+                message_reaction::Relation::Message.def(),
+            )
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(message_reaction::Column::MessageId.is_in(message_ids.to_vec()))
+            .order_by_asc(message_reaction::Column::Id)
+            .into_tuple::<(i32, String, i32)>()
+            .all(db)
+            .await?;
+
+        let mut reactions: HashMap<i32, Vec<Reaction>> = HashMap::new();
+        for (message_id, emoji, user_id) in rows {
+            let message_reactions = reactions.entry(message_id).or_default();
+            match message_reactions
+                .iter_mut()
+                .find(|reaction| reaction.emoji == emoji)
+            {
+                Some(reaction) => reaction.user_ids.push(user_id),
+                None => message_reactions.push(Reaction {
+                    emoji,
+                    user_ids: vec![user_id],
+                }),
+            }
+        }
+        Ok(Reactions(reactions))
+    }
+
+    /// The files attached to the given messages, by message id.
+    pub async fn get_attachments(
+        db: &DbConn,
+        member: &ChatMember,
+        message_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<attachment::Model>>, DbErr> {
+        if message_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let rows = attachment::Entity::find()
+            .join(JoinType::InnerJoin, attachment::Relation::Message.def())
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(attachment::Column::MessageId.is_in(message_ids.to_vec()))
+            .order_by_asc(attachment::Column::Id)
+            .all(db)
+            .await?;
+
+        let mut attachments: HashMap<i32, Vec<attachment::Model>> = HashMap::new();
+        for attachment in rows {
+            attachments
+                .entry(attachment.message_id)
+                .or_default()
+                .push(attachment);
+        }
+        Ok(attachments)
+    }
+
+    /// An attachment of a message in the member's chat. `None` once the message
+    /// was deleted, so its files can't be downloaded anymore.
+    pub async fn get_attachment(
+        db: &DbConn,
+        member: &ChatMember,
+        attachment_id: i32,
+    ) -> Result<Option<attachment::Model>, DbErr> {
+        attachment::Entity::find_by_id(attachment_id)
+            .join(JoinType::InnerJoin, attachment::Relation::Message.def())
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(message::Column::DeletedAt.is_null())
+            .one(db)
+            .await
+    }
+
+    /// The root of the thread `message_id` belongs to and every reply in it,
+    /// oldest first. `None` if the message is not in the member's chat.
+    pub async fn get_thread(
+        db: &DbConn,
+        member: &ChatMember,
+        message_id: i32,
+    ) -> Result<Option<(message::Model, Vec<message::Model>)>, DbErr> {
+        let Some(message) = message::Entity::find_by_id(message_id)
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .one(db)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let root = match message.thread_root_id {
+            Some(root_id) => match message::Entity::find_by_id(root_id).one(db).await? {
+                Some(root) => root,
+                None => return Ok(None),
+            },
+            None => message,
+        };
+
+        let replies = message::Entity::find()
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(message::Column::ThreadRootId.eq(root.id))
+            .order_by_asc(message::Column::Timestamp)
+            .order_by_asc(message::Column::Id)
+            .all(db)
+            .await?;
+        Ok(Some((root, replies)))
+    }
+
+    /// Messages posted after `after_id`, oldest first, for clients catching up on a chat.
+    pub async fn get_chat_messages_after(
+        db: &DbConn,
+        member: &ChatMember,
+        after_id: i32,
+        limit: u64,
+    ) -> Result<Vec<message::Model>, DbErr> {
+        message::Entity::find()
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .filter(message::Column::Id.gt(after_id))
+            .order_by_asc(message::Column::Id)
+            .limit(limit)
+            .all(db)
+            .await
+    }
+
+    /// Messages matching `query` in the chats the user belongs to, newest first.
+    /// The query takes the syntax of web search boxes, e.g. `"exact phrase" -word`.
+    pub async fn search_messages(
+        db: &DbConn,
+        user_id: i32,
+        query: &str,
+        limit: u64,
+    ) -> Result<Vec<SearchHit>, DbErr> {
+        let options = format!(
+            "StartSel={MATCH_START}, StopSel={MATCH_END}, MaxWords=30, MinWords=10, \
+            MaxFragments=2, FragmentDelimiter=\" … \""
+        );
+        db.query_all(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"SELECT "message"."id", "message"."chat_id",
+                COALESCE("chat"."name", "partner"."username", 'Chat ' || "chat"."id"),
+                "user"."username", "message"."timestamp",
+                ts_headline('english', "message"."text", "query", $3)
+            FROM "message"
+            CROSS JOIN websearch_to_tsquery('english', $2) "query"
+            JOIN "user_in_chat" ON "user_in_chat"."chat_id" = "message"."chat_id"
+                AND "user_in_chat"."user_id" = $1
+            JOIN "chat" ON "chat"."id" = "message"."chat_id"
+            JOIN "user" ON "user"."id" = "message"."user_id"
+            LEFT JOIN LATERAL (
+                SELECT "user"."username" FROM "user_in_chat" "other"
+                JOIN "user" ON "user"."id" = "other"."user_id"
+                WHERE "other"."chat_id" = "chat"."id" AND "other"."user_id" <> $1
+                LIMIT 1
+            ) "partner" ON "chat"."direct_key" IS NOT NULL
+            WHERE "message"."search" @@ "query"
+                AND "message"."deleted_at" IS NULL
+                AND NOT "message"."is_system"
+            ORDER BY "message"."timestamp" DESC, "message"."id" DESC
+            LIMIT $4"#,
+            [
+                user_id.into(),
+                query.into(),
+                options.into(),
+                (limit as i64).into(),
+            ],
+        ))
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(SearchHit {
+                message_id: row.try_get_by_index(0)?,
+                chat_id: row.try_get_by_index(1)?,
+                chat_name: row.try_get_by_index(2)?,
+                username: row.try_get_by_index(3)?,
+                timestamp: row.try_get_by_index(4)?,
+                snippets: snippets(&row.try_get_by_index::<String>(5)?),
+            })
+        })
+        .collect()
+    }
+
+    /// Returns the direct chat between the two users, creating it on first use.
+    pub async fn get_or_create_direct_chat(
+        db: &DbConn,
+        user_id: i32,
+        other_user_id: i32,
+    ) -> Result<chat::Model, DbErr> {
+        let direct_key = format!(
+            "{}:{}",
+            user_id.min(other_user_id),
+            user_id.max(other_user_id)
+        );
+        if let Some(chat) = Self::get_chat_by_direct_key(db, &direct_key).await? {
+            return Ok(chat);
+        }
+
+        let key = direct_key.clone();
+        let created = db
+            .transaction::<_, chat::Model, DbErr>(|txn| {
+                Box::pin(async move {
+                    let chat = chat::ActiveModel {
+                        direct_key: Set(Some(key)),
+                        ..Default::default()
+                    };
+                    let chat = Self::insert_chat(txn, chat).await?;
+                    Self::insert_members(txn, chat.id, &[user_id, other_user_id], ChatRole::Member)
+                        .await?;
+                    Ok(chat)
+                })
+            })
+            .await;
+
+        match created {
+            Ok(chat) => Ok(chat),
+            // A concurrent request created the same chat first, the unique key lets us reuse it.
+            Err(TransactionError::Transaction(err)) => {
+                Self::get_chat_by_direct_key(db, &direct_key)
+                    .await?
+                    .ok_or(err)
+            }
+            Err(TransactionError::Connection(err)) => Err(err),
+        }
+    }
+
+    /// Creates a group owned by `owner_id`. The owner is always added as a member.
+    pub async fn create_group_chat(
+        db: &DbConn,
+        owner_id: i32,
+        name: String,
+        topic: Option<String>,
+        avatar_url: Option<String>,
+        mut member_ids: Vec<i32>,
+    ) -> Result<chat::Model, DbErr> {
+        member_ids.retain(|member_id| *member_id != owner_id);
+        db.transaction::<_, chat::Model, DbErr>(|txn| {
+            Box::pin(async move {
+                let chat = chat::ActiveModel {
+                    name: Set(Some(name)),
+                    topic: Set(topic),
+                    avatar_url: Set(avatar_url),
+                    owner_id: Set(Some(owner_id)),
+                    ..Default::default()
+                };
+                let chat = Self::insert_chat(txn, chat).await?;
+                Self::insert_members(txn, chat.id, &[owner_id], ChatRole::Owner).await?;
+                if !member_ids.is_empty() {
+                    Self::insert_members(txn, chat.id, &member_ids, ChatRole::Member).await?;
+                }
+                Ok(chat)
+            })
+        })
+        .await
+        .map_err(|err| match err {
+            TransactionError::Connection(err) | TransactionError::Transaction(err) => err,
+        })
+    }
+
+    async fn get_chat_by_direct_key(
+        db: &DbConn,
+        direct_key: &str,
+    ) -> Result<Option<chat::Model>, DbErr> {
+        chat::Entity::find()
+            .filter(chat::Column::DirectKey.eq(direct_key))
+            .one(db)
+            .await
     }
 
-    async fn upseart_chat(db: &DbConn, chat_id: i64) -> Result<(), DbErr> {
+    async fn insert_chat(
+        db: &impl ConnectionTrait,
+        chat: chat::ActiveModel,
+    ) -> Result<chat::Model, DbErr> {
         let seconds_since_epoch = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards!")
             .as_secs() as i32;
-        let new_chat = chat::ActiveModel {
-            id: Set(chat_id as i32),
+
+        chat::ActiveModel {
             last_changed_timestamp: Set(seconds_since_epoch),
+            ..chat
+        }
+        .insert(db)
+        .await
+    }
+
+    async fn insert_members(
+        db: &impl ConnectionTrait,
+        chat_id: i32,
+        user_ids: &[i32],
+        role: ChatRole,
+    ) -> Result<(), DbErr> {
+        let mut user_ids = user_ids.to_vec();
+        user_ids.sort_unstable();
+        user_ids.dedup();
+
+        user_in_chat::Entity::insert_many(user_ids.into_iter().map(|user_id| {
+            user_in_chat::ActiveModel {
+                chat_id: Set(chat_id),
+                user_id: Set(user_id),
+                role: Set(role),
+                ..Default::default()
+            }
+        }))
+        .exec(db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_members(
+        db: &DbConn,
+        member: &ChatMember,
+    ) -> Result<Vec<MemberSummary>, DbErr> {
+        let members = user_in_chat::Entity::find()
+            .select_only()
+            .column(user_in_chat::Column::UserId)
+            .column(user::Column::Username)
+            .column(user_in_chat::Column::Role)
+            .column(user_in_chat::Column::LastReadMessageId)
+            .join(JoinType::InnerJoin, user_in_chat::Relation::User.def())
+            .filter(user_in_chat::Column::ChatId.eq(member.chat_id))
+            .order_by_asc(user::Column::Username)
+            .into_tuple::<(i32, String, ChatRole, Option<i32>)>()
+            .all(db)
+            .await?;
+
+        Ok(members
+            .into_iter()
+            .map(
+                |(user_id, username, role, last_read_message_id)| MemberSummary {
+                    user_id,
+                    username,
+                    role,
+                    last_read_message_id,
+                },
+            )
+            .collect())
+    }
+
+    pub async fn get_member_ids(db: &DbConn, member: &ChatMember) -> Result<Vec<i32>, DbErr> {
+        user_in_chat::Entity::find()
+            .select_only()
+            .column(user_in_chat::Column::UserId)
+            .filter(user_in_chat::Column::ChatId.eq(member.chat_id))
+            .into_tuple()
+            .all(db)
+            .await
+    }
+
+    pub async fn get_latest_message_id(
+        db: &DbConn,
+        member: &ChatMember,
+    ) -> Result<Option<i32>, DbErr> {
+        let latest: Option<Option<i32>> = message::Entity::find()
+            .select_only()
+            .column_as(message::Column::Id.max(), "latest")
+            .filter(message::Column::ChatId.eq(member.chat_id))
+            .into_tuple()
+            .one(db)
+            .await?;
+        Ok(latest.flatten())
+    }
+
+    /// Moves the member's read marker forward to `message_id`. Returns whether
+    /// it moved, so callers only announce receipts that changed something.
+    pub async fn mark_read(
+        db: &DbConn,
+        member: &ChatMember,
+        message_id: i32,
+    ) -> Result<bool, DbErr> {
+        let result = user_in_chat::Entity::update_many()
+            .col_expr(
+                user_in_chat::Column::LastReadMessageId,
+                sea_query::Expr::value(message_id),
+            )
+            .filter(user_in_chat::Column::ChatId.eq(member.chat_id))
+            .filter(user_in_chat::Column::UserId.eq(member.user_id))
+            .filter(
+                Condition::any()
+                    .add(user_in_chat::Column::LastReadMessageId.is_null())
+                    .add(user_in_chat::Column::LastReadMessageId.lt(message_id)),
+            )
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Marks everything currently in the chat as read, returning the new marker if it moved.
+    pub async fn mark_chat_read(db: &DbConn, member: &ChatMember) -> Result<Option<i32>, DbErr> {
+        let Some(latest) = Self::get_latest_message_id(db, member).await? else {
+            return Ok(None);
         };
+        Ok(Self::mark_read(db, member, latest).await?.then_some(latest))
+    }
+
+    /// Adds the users that are not members yet, returning the ids that were added.
+    pub async fn add_members(
+        db: &DbConn,
+        member: &ChatMember,
+        user_ids: Vec<i32>,
+    ) -> Result<Vec<i32>, DbErr> {
+        let existing: Vec<i32> = user_in_chat::Entity::find()
+            .select_only()
+            .column(user_in_chat::Column::UserId)
+            .filter(user_in_chat::Column::ChatId.eq(member.chat_id))
+            .into_tuple()
+            .all(db)
+            .await?;
 
-        let query = chat::Entity::insert(new_chat)
-            .on_conflict(OnConflict::columns([chat::Column::Id]))
-            .build(DbBackend::Postgres);
+        let mut added: Vec<i32> = user_ids
+            .into_iter()
+            .filter(|user_id| !existing.contains(user_id))
+            .collect();
+        added.sort_unstable();
+        added.dedup();
+
+        if !added.is_empty() {
+            Self::insert_members(db, member.chat_id, &added, ChatRole::Member).await?;
+        }
+        Ok(added)
+    }
 
-        db.execute(query).await?;
+    pub async fn remove_member(
+        db: &DbConn,
+        member: &ChatMember,
+        user_id: i32,
+    ) -> Result<(), DbErr> {
+        user_in_chat::Entity::delete_many()
+            .filter(user_in_chat::Column::ChatId.eq(member.chat_id))
+            .filter(user_in_chat::Column::UserId.eq(user_id))
+            .exec(db)
+            .await?;
         Ok(())
     }
 
+    pub async fn set_member_role(
+        db: &DbConn,
+        member: &ChatMember,
+        user_id: i32,
+        role: ChatRole,
+    ) -> Result<(), DbErr> {
+        Self::update_role(db, member.chat_id, user_id, role).await
+    }
+
+    /// Makes `new_owner_id` the owner, demoting the current owner to admin.
+    pub async fn transfer_ownership(
+        db: &DbConn,
+        owner: &ChatMember,
+        new_owner_id: i32,
+    ) -> Result<(), DbErr> {
+        let ChatMember {
+            chat_id, user_id, ..
+        } = *owner;
+        db.transaction::<_, (), DbErr>(|txn| {
+            Box::pin(async move {
+                Self::update_role(txn, chat_id, user_id, ChatRole::Admin).await?;
+                Self::update_role(txn, chat_id, new_owner_id, ChatRole::Owner).await?;
+                chat::ActiveModel {
+                    id: ActiveValue::Unchanged(chat_id),
+                    owner_id: Set(Some(new_owner_id)),
+                    ..Default::default()
+                }
+                .update(txn)
+                .await?;
+                Ok(())
+            })
+        })
+        .await
+        .map_err(|err| match err {
+            TransactionError::Connection(err) | TransactionError::Transaction(err) => err,
+        })
+    }
+
+    async fn update_role(
+        db: &impl ConnectionTrait,
+        chat_id: i32,
+        user_id: i32,
+        role: ChatRole,
+    ) -> Result<(), DbErr> {
+        user_in_chat::Entity::update_many()
+            .col_expr(user_in_chat::Column::Role, sea_query::Expr::value(role))
+            .filter(user_in_chat::Column::ChatId.eq(chat_id))
+            .filter(user_in_chat::Column::UserId.eq(user_id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_chat(db: &DbConn, member: &ChatMember) -> Result<ChatSummary, DbErr> {
+        let chat = chat::Entity::find_by_id(member.chat_id)
+            .one(db)
+            .await?
+            .ok_or(DbErr::RecordNotFound(format!("chat {}", member.chat_id)))?;
+
+        let mut summaries = Self::summarize_chats(db, member.user_id, vec![chat]).await?;
+        Ok(summaries.remove(0))
+    }
+
+    /// Every chat the user is a member of.
+    pub async fn get_chat_ids(db: &DbConn, user_id: i32) -> Result<Vec<i32>, DbErr> {
+        user_in_chat::Entity::find()
+            .select_only()
+            .column(user_in_chat::Column::ChatId)
+            .filter(user_in_chat::Column::UserId.eq(user_id))
+            .into_tuple()
+            .all(db)
+            .await
+    }
+
+    /// The users the given one has a direct chat with, whose sidebars show
+    /// whether they are online.
+    pub async fn get_direct_partner_ids(db: &DbConn, user_id: i32) -> Result<Vec<i32>, DbErr> {
+        let chat_ids: Vec<i32> = user_in_chat::Entity::find()
+            .select_only()
+            .column(user_in_chat::Column::ChatId)
+            .join(JoinType::InnerJoin, user_in_chat::Relation::Chat.def())
+            .filter(user_in_chat::Column::UserId.eq(user_id))
+            .filter(chat::Column::DirectKey.is_not_null())
+            .into_tuple()
+            .all(db)
+            .await?;
+        if chat_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        user_in_chat::Entity::find()
+            .select_only()
+            .column(user_in_chat::Column::UserId)
+            .filter(user_in_chat::Column::ChatId.is_in(chat_ids))
+            .filter(user_in_chat::Column::UserId.ne(user_id))
+            .distinct()
+            .into_tuple()
+            .all(db)
+            .await
+    }
+
     pub async fn get_chats_by_user_id(
         db: &DbConn,
         user_id: i64,
         page: u64,
         chats_per_page: u64,
-    ) -> Result<(Vec<chat::Model>, u64), DbErr> {
+    ) -> Result<(Vec<ChatSummary>, u64), DbErr> {
         let paginator = chat::Entity::find()
             .join(JoinType::LeftJoin, chat::Relation::UserInChat.def())
             .filter(user_in_chat::Column::UserId.eq(user_id))
-            .order_by_asc(chat::Column::LastChangedTimestamp)
+            .order_by_desc(chat::Column::LastChangedTimestamp)
+            .order_by_desc(chat::Column::Id)
             .paginate(db, chats_per_page);
         let num_page = paginator.num_pages().await?;
-        paginator.fetch_page(page - 1).await.map(|p| (p, num_page))
+        let chats = paginator.fetch_page(page - 1).await?;
+        let summaries = Self::summarize_chats(db, user_id as i32, chats).await?;
+        Ok((summaries, num_page))
+    }
+
+    /// Resolves display names, looking up the other participant of every direct
+    /// chat in a single query, and fetches unread counts and the newest message
+    /// of every chat in another.
+    async fn summarize_chats(
+        db: &DbConn,
+        user_id: i32,
+        chats: Vec<chat::Model>,
+    ) -> Result<Vec<ChatSummary>, DbErr> {
+        let direct_chat_ids: Vec<i32> = chats
+            .iter()
+            .filter(|chat| chat.direct_key.is_some())
+            .map(|chat| chat.id)
+            .collect();
+
+        let mut partners: HashMap<i32, (i32, String)> = HashMap::new();
+        if !direct_chat_ids.is_empty() {
+            partners = user_in_chat::Entity::find()
+                .select_only()
+                .column(user_in_chat::Column::ChatId)
+                .column(user_in_chat::Column::UserId)
+                .column(user::Column::Username)
+                .join(JoinType::InnerJoin, user_in_chat::Relation::User.def())
+                .filter(user_in_chat::Column::ChatId.is_in(direct_chat_ids))
+                .filter(user_in_chat::Column::UserId.ne(user_id))
+                .into_tuple::<(i32, i32, String)>()
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|(chat_id, partner_id, username)| (chat_id, (partner_id, username)))
+                .collect();
+        }
+
+        let chat_ids: Vec<i32> = chats.iter().map(|chat| chat.id).collect();
+        let mut activity: HashMap<i32, (i64, Option<LastMessage>)> = db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                r#"SELECT "user_in_chat"."chat_id",
+                    (SELECT COUNT(*) FROM "message"
+                        WHERE "message"."chat_id" = "user_in_chat"."chat_id"
+                            AND "message"."user_id" <> $1
+                            AND "message"."deleted_at" IS NULL
+                            AND "message"."id" > COALESCE("user_in_chat"."last_read_message_id", 0)),
+                    "last"."text", "last"."username", "last"."timestamp", "last"."is_system",
+                    "last"."deleted"
+                FROM "user_in_chat"
+                LEFT JOIN LATERAL (
+                    SELECT "message"."text", "user"."username", "message"."timestamp",
+                        "message"."is_system", "message"."deleted_at" IS NOT NULL AS "deleted"
+                    FROM "message" JOIN "user" ON "user"."id" = "message"."user_id"
+                    WHERE "message"."chat_id" = "user_in_chat"."chat_id"
+                    ORDER BY "message"."timestamp" DESC, "message"."id" DESC
+                    LIMIT 1
+                ) "last" ON TRUE
+                WHERE "user_in_chat"."user_id" = $1 AND "user_in_chat"."chat_id" = ANY($2)"#,
+                [user_id.into(), chat_ids.into()],
+            ))
+            .await?
+            .iter()
+            .map(|row| {
+                let last_message = match row.try_get_by_index::<Option<String>>(2)? {
+                    Some(text) => Some(LastMessage {
+                        text,
+                        username: row.try_get_by_index(3)?,
+                        timestamp: row.try_get_by_index(4)?,
+                        is_system: row.try_get_by_index(5)?,
+                        deleted: row.try_get_by_index(6)?,
+                    }),
+                    None => None,
+                };
+                Ok((row.try_get_by_index(0)?, (row.try_get_by_index(1)?, last_message)))
+            })
+            .collect::<Result<_, DbErr>>()?;
+
+        Ok(chats
+            .into_iter()
+            .map(|chat| {
+                let partner = partners.remove(&chat.id);
+                let partner_id = partner.as_ref().map(|(partner_id, _)| *partner_id);
+                let display_name = match (&chat.name, partner) {
+                    (Some(name), _) => name.clone(),
+                    (None, Some((_, username))) => username,
+                    (None, None) => format!("Chat {}", chat.id),
+                };
+                let (unread, last_message) = activity.remove(&chat.id).unwrap_or_default();
+                ChatSummary {
+                    chat,
+                    display_name,
+                    unread,
+                    partner_id,
+                    last_message,
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(headline: &str) -> Vec<(String, bool)> {
+        snippets(headline)
+            .into_iter()
+            .map(|snippet| (snippet.text, snippet.matched))
+            .collect()
+    }
+
+    #[test]
+    fn snippets_split_on_the_match_marks() {
+        assert_eq!(
+            parts("the \u{1}quick\u{2} brown \u{1}fox\u{2}"),
+            [
+                ("the ".to_owned(), false),
+                ("quick".to_owned(), true),
+                (" brown ".to_owned(), false),
+                ("fox".to_owned(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn snippets_leave_out_empty_parts() {
+        assert_eq!(parts("\u{1}match\u{2}"), [("match".to_owned(), true)]);
+        assert!(parts("").is_empty());
+    }
+
+    #[test]
+    fn snippets_keep_markup_as_text() {
+        assert_eq!(
+            parts("<b>\u{1}<script>\u{2}"),
+            [("<b>".to_owned(), false), ("<script>".to_owned(), true)]
+        );
     }
 }
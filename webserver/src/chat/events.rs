@@ -0,0 +1,285 @@
+use askama::Template;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::chat::database::{Reaction, Reactions, ReplyPreview};
+use crate::chat::receipts::SeenByTemplate;
+use crate::chat::routes::live_chat::{ChatError, MessageList, MessageUpdate, Presence};
+use crate::chat::routes::reactions::ReactionBar;
+use crate::chat::typing::TypingIndicator;
+use crate::entities::{attachment, message};
+
+/// Frames the browser sends over the live chat socket. htmx's `ws-send` posts
+/// the form values as JSON, so forms carry the tag in a hidden `type` input.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientEvent {
+    Send {
+        message: String,
+    },
+    Edit {
+        message_id: i32,
+        text: String,
+    },
+    Delete {
+        message_id: i32,
+    },
+    Typing,
+    Read,
+    Ping,
+    /// Sent when the socket opens, with the newest message the page already shows.
+    Resume {
+        after: Option<i32>,
+        /// Set when the page shows an older window, whose newer messages are
+        /// loaded through "load newer" instead of being replayed.
+        #[serde(default)]
+        window: bool,
+        /// Position of the last event received from the broker's log, so edits,
+        /// deletions and the like published while away are replayed too.
+        #[serde(default)]
+        cursor: Option<String>,
+    },
+}
+
+/// Events published on a [`Topic`](crate::chat::broker::Topic) and rendered for each socket.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// `reply_to` is looked up once when publishing, not by every socket.
+    Message {
+        message: message::Model,
+        #[serde(default)]
+        reply_to: Option<ReplyPreview>,
+        #[serde(default)]
+        attachments: Vec<attachment::Model>,
+    },
+    /// Carries the reactions too, since the whole message is swapped.
+    Edit {
+        message: message::Model,
+        #[serde(default)]
+        reply_to: Option<ReplyPreview>,
+        #[serde(default)]
+        reactions: Vec<Reaction>,
+        #[serde(default)]
+        attachments: Vec<attachment::Model>,
+    },
+    /// Built with [`ServerEvent::delete`], the message comes without its text.
+    Delete {
+        message: message::Model,
+    },
+    Presence {
+        user_id: i32,
+        online: bool,
+    },
+    /// Ephemeral, never stored. Sockets expire it on their own after
+    /// [`TYPING_TIMEOUT`](crate::chat::typing::TYPING_TIMEOUT).
+    Typing {
+        user_id: i32,
+        username: String,
+    },
+    /// A member's read marker moved forward to `message_id`.
+    Read {
+        user_id: i32,
+        username: String,
+        message_id: i32,
+    },
+    Error {
+        error: String,
+    },
+    /// Published on `user:{id}` when the chat's row in the user's sidebar is out
+    /// of date, e.g. after a new message, a read or a membership change.
+    ChatChanged {
+        chat_id: i32,
+    },
+    /// The reactions of a message changed, `reactions` are all of them.
+    Reactions {
+        chat_id: i32,
+        message_id: i32,
+        reactions: Vec<Reaction>,
+    },
+}
+
+impl ServerEvent {
+    /// A deleted message, with its text blanked so it isn't published through
+    /// the broker or kept in a stream log.
+    pub fn delete(message: message::Model) -> Self {
+        ServerEvent::Delete {
+            message: message::Model {
+                text: String::new(),
+                ..message
+            },
+        }
+    }
+
+    /// Renders the event as htmx out-of-band fragments for the given viewer.
+    pub fn render(self, user_id: i32) -> askama::Result<String> {
+        match self {
+            ServerEvent::Message {
+                message,
+                reply_to,
+                attachments,
+            } => MessageList {
+                attachments: HashMap::from([(message.id, attachments)]),
+                messages: vec![message],
+                previews: previews(reply_to),
+                reactions: Reactions::default(),
+                user_id,
+            }
+            .render(),
+            ServerEvent::Edit {
+                message,
+                reply_to,
+                reactions,
+                attachments,
+            } => MessageUpdate {
+                reactions: Reactions::from((message.id, reactions)),
+                attachments: HashMap::from([(message.id, attachments)]),
+                messages: vec![message],
+                previews: previews(reply_to),
+                user_id,
+                oob: true,
+            }
+            .render(),
+            ServerEvent::Delete { message } => MessageUpdate {
+                messages: vec![message],
+                previews: HashMap::new(),
+                reactions: Reactions::default(),
+                attachments: HashMap::new(),
+                user_id,
+                oob: true,
+            }
+            .render(),
+            ServerEvent::Reactions {
+                chat_id,
+                message_id,
+                reactions,
+            } => ReactionBar {
+                chat_id,
+                message_id,
+                reactions: Reactions::from((message_id, reactions)),
+                user_id,
+                reactions_oob: true,
+            }
+            .render(),
+            ServerEvent::Presence { user_id, online } => Presence { user_id, online }.render(),
+            ServerEvent::Typing { username, .. } => TypingIndicator {
+                names: vec![username],
+            }
+            .render(),
+            // Sockets track receipts with `SeenBy`, this only covers a lone event.
+            ServerEvent::Read { username, .. } => SeenByTemplate {
+                names: vec![username],
+            }
+            .render(),
+            ServerEvent::Error { error } => ChatError { error }.render(),
+            // The row depends on the viewer's membership and unread count, so the
+            // user stream looks it up with `ChatRowUpdate` instead.
+            ServerEvent::ChatChanged { .. } => Ok(String::new()),
+        }
+    }
+}
+
+/// The lookup message templates take, for a single message.
+pub fn previews(reply_to: Option<ReplyPreview>) -> HashMap<i32, ReplyPreview> {
+    reply_to
+        .into_iter()
+        .map(|preview| (preview.message_id, preview))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: i32) -> message::Model {
+        message::Model {
+            id,
+            text: format!("message {id}"),
+            user_id: 2,
+            chat_id: 1,
+            timestamp: id,
+            is_system: false,
+            edited_at: None,
+            deleted_at: None,
+            reply_to_id: None,
+            thread_root_id: None,
+        }
+    }
+
+    #[test]
+    fn replies_quote_the_message_they_answer() {
+        let html = ServerEvent::Message {
+            message: message::Model {
+                reply_to_id: Some(1),
+                thread_root_id: Some(1),
+                ..message(2)
+            },
+            reply_to: Some(ReplyPreview {
+                message_id: 1,
+                username: "bob".to_owned(),
+                text: "<i>first</i>".to_owned(),
+                deleted: false,
+            }),
+            attachments: vec![],
+        }
+        .render(1)
+        .unwrap();
+        assert!(html.contains("&lt;i&gt;first&lt;/i&gt;"));
+        // Open thread panels get the reply too.
+        assert!(html.contains(r#"hx-swap-oob="beforeend:#thread-replies-1""#));
+    }
+
+    #[test]
+    fn replies_to_deleted_messages_leave_out_their_text() {
+        let html = ServerEvent::Message {
+            message: message::Model {
+                reply_to_id: Some(1),
+                ..message(2)
+            },
+            reply_to: Some(ReplyPreview {
+                message_id: 1,
+                username: "bob".to_owned(),
+                text: "gone".to_owned(),
+                deleted: true,
+            }),
+            attachments: vec![],
+        }
+        .render(1)
+        .unwrap();
+        assert!(html.contains("This message was deleted"));
+        assert!(!html.contains("gone"));
+    }
+
+    #[test]
+    fn reactions_count_and_mark_the_viewers_own() {
+        let event = || ServerEvent::Reactions {
+            chat_id: 1,
+            message_id: 7,
+            reactions: vec![Reaction {
+                emoji: "🎉".to_owned(),
+                user_ids: vec![1, 2],
+            }],
+        };
+        let own = event().render(1).unwrap();
+        assert!(own.contains(r#"id="reactions-7""#));
+        assert!(own.contains("🎉 2"));
+        assert!(own.contains("border-blue-500"));
+        assert!(!event().render(3).unwrap().contains("border-blue-500"));
+    }
+
+    #[test]
+    fn edits_keep_the_reactions() {
+        let html = ServerEvent::Edit {
+            message: message(7),
+            reply_to: None,
+            reactions: vec![Reaction {
+                emoji: "👍".to_owned(),
+                user_ids: vec![2],
+            }],
+            attachments: vec![],
+        }
+        .render(1)
+        .unwrap();
+        assert!(html.contains("👍 1"));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::chat::broker::Topic;
+use crate::chat::events::ServerEvent;
+
+/// How many events a slow socket may fall behind before it starts missing them.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// An event together with its id in the broker's log, for brokers that keep one.
+#[derive(Clone)]
+struct Delivery {
+    position: Option<String>,
+    event: ServerEvent,
+}
+
+struct Channel {
+    sender: broadcast::Sender<Delivery>,
+    /// Id of the last event dispatched from the broker's log, `None` without one.
+    position: Option<String>,
+}
+
+/// Fans events out to the sockets of this process, so a broker needs one
+/// subscription no matter how many topics are open.
+#[derive(Default)]
+pub struct EventHub {
+    channels: Mutex<HashMap<Topic, Channel>>,
+}
+
+impl EventHub {
+    pub fn subscribe(self: &Arc<Self>, topic: Topic) -> Subscription {
+        self.subscribe_at(topic, None)
+    }
+
+    /// Subscribes to a topic read from a log. `newest` becomes the position the
+    /// topic is read from if this opens it, otherwise the subscription joins at
+    /// the topic's current position. Both happen under the lock dispatching
+    /// takes, so the subscription gets exactly the events after its start.
+    pub fn subscribe_at(self: &Arc<Self>, topic: Topic, newest: Option<String>) -> Subscription {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = channels.entry(topic).or_insert_with(|| Channel {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            position: newest,
+        });
+        Subscription {
+            hub: self.clone(),
+            topic,
+            receiver: channel.sender.subscribe(),
+            start: channel.position.clone(),
+            position: None,
+        }
+    }
+
+    /// Where each open topic read from a log continues.
+    pub fn positions(&self) -> Vec<(Topic, String)> {
+        self.channels
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(topic, channel)| Some((*topic, channel.position.clone()?)))
+            .collect()
+    }
+
+    pub fn dispatch(&self, topic: Topic, event: ServerEvent) {
+        if let Some(channel) = self.channels.lock().unwrap().get(&topic) {
+            // No receivers just means the last socket is on its way out.
+            let _ = channel.sender.send(Delivery {
+                position: None,
+                event,
+            });
+        }
+    }
+
+    /// Dispatches an event read from a log and moves the topic's position past it.
+    /// Events at or before the position were seen by every subscriber already,
+    /// which happens when the topic was reopened while they were being read.
+    pub fn dispatch_at(&self, topic: Topic, position: String, event: ServerEvent) {
+        if let Some(channel) = self.channels.lock().unwrap().get_mut(&topic) {
+            if channel
+                .position
+                .as_deref()
+                .is_some_and(|current| !is_after(&position, current))
+            {
+                return;
+            }
+            channel.position = Some(position.clone());
+            let _ = channel.sender.send(Delivery {
+                position: Some(position),
+                event,
+            });
+        }
+    }
+}
+
+/// Whether log position `a` comes after `b`. Positions are Redis stream ids,
+/// `<milliseconds>-<sequence>`, which don't sort as strings.
+fn is_after(a: &str, b: &str) -> bool {
+    fn parse(id: &str) -> (u64, u64) {
+        let (millis, sequence) = id.split_once('-').unwrap_or((id, "0"));
+        (
+            millis.parse().unwrap_or_default(),
+            sequence.parse().unwrap_or_default(),
+        )
+    }
+    parse(a) > parse(b)
+}
+
+/// A socket's view of one topic. The topic's channel is dropped from the hub
+/// together with its last subscription.
+pub struct Subscription {
+    hub: Arc<EventHub>,
+    topic: Topic,
+    receiver: broadcast::Receiver<Delivery>,
+    /// The log position the subscription started after, see [`EventHub::subscribe_at`].
+    start: Option<String>,
+    /// The log position of the last event received.
+    position: Option<String>,
+}
+
+impl Subscription {
+    pub fn topic(&self) -> Topic {
+        self.topic
+    }
+
+    pub fn start(&self) -> Option<&str> {
+        self.start.as_deref()
+    }
+
+    /// Where in the broker's log the last received event is, for the client to
+    /// resume from after a reconnect.
+    pub fn position(&self) -> Option<&str> {
+        self.position.as_deref()
+    }
+
+    /// The next event, skipping over any this subscription fell too far behind to see.
+    /// Returns `None` once the hub is gone.
+    pub async fn recv(&mut self) -> Option<ServerEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(delivery) => {
+                    if delivery.position.is_some() {
+                        self.position = delivery.position;
+                    }
+                    return Some(delivery.event);
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    info!("{} subscriber skipped {skipped} events", self.topic);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut channels = self.hub.channels.lock().unwrap();
+        // Our own receiver is still counted until after this runs.
+        if channels
+            .get(&self.topic)
+            .is_some_and(|channel| channel.sender.receiver_count() <= 1)
+        {
+            channels.remove(&self.topic);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(message_id: i32) -> ServerEvent {
+        ServerEvent::Read {
+            user_id: 1,
+            username: "alice".to_owned(),
+            message_id,
+        }
+    }
+
+    #[test]
+    fn stream_ids_compare_numerically() {
+        assert!(is_after("10-0", "9-0"));
+        assert!(is_after("5-10", "5-9"));
+        assert!(!is_after("5-1", "5-1"));
+        assert!(!is_after("9-5", "10-0"));
+        assert!(is_after("1-0", "0-0"));
+    }
+
+    #[tokio::test]
+    async fn subscriptions_join_at_the_topic_position() {
+        let hub = Arc::new(EventHub::default());
+        let first = hub.subscribe_at(Topic::Chat(1), Some("5-0".to_owned()));
+        assert_eq!(first.start(), Some("5-0"));
+
+        hub.dispatch_at(Topic::Chat(1), "6-0".to_owned(), read(6));
+        // A later subscriber's own read of the stream is older, the topic's position wins.
+        let second = hub.subscribe_at(Topic::Chat(1), Some("5-0".to_owned()));
+        assert_eq!(second.start(), Some("6-0"));
+        assert!(hub.positions() == [(Topic::Chat(1), "6-0".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn dispatch_at_skips_entries_already_dispatched() {
+        let hub = Arc::new(EventHub::default());
+        let mut subscription = hub.subscribe_at(Topic::Chat(1), Some("5-0".to_owned()));
+        hub.dispatch_at(Topic::Chat(1), "4-0".to_owned(), read(4));
+        hub.dispatch_at(Topic::Chat(1), "6-0".to_owned(), read(6));
+
+        let Some(ServerEvent::Read { message_id, .. }) = subscription.recv().await else {
+            panic!("expected a read event");
+        };
+        assert_eq!(message_id, 6);
+        assert_eq!(subscription.position(), Some("6-0"));
+    }
+
+    #[test]
+    fn the_last_subscription_closes_the_topic() {
+        let hub = Arc::new(EventHub::default());
+        let first = hub.subscribe_at(Topic::Chat(1), Some("0-0".to_owned()));
+        let second = hub.subscribe(Topic::Chat(1));
+        drop(first);
+        assert_eq!(hub.positions().len(), 1);
+        drop(second);
+        assert!(hub.positions().is_empty());
+    }
+}
@@ -1,2 +1,10 @@
+mod activity;
+pub mod broker;
 mod database;
+mod events;
+mod hub;
+pub mod presence;
+mod receipts;
 pub mod routes;
+pub mod storage;
+mod typing;
@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use tracing::info;
+
+use crate::chat::broker::Topic;
+use crate::chat::database::ChatDatabase;
+use crate::chat::events::ServerEvent;
+use crate::AppState;
+
+/// How long a connection counts as online without a heartbeat, so sockets of a
+/// crashed process drop out on their own.
+pub const PRESENCE_TTL: Duration = Duration::from_secs(30);
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keeps track of which users have open sockets, possibly across processes.
+#[async_trait]
+pub trait PresenceTracker: Send + Sync {
+    /// Registers a connection, returning whether the user just came online.
+    async fn connect(&self, user_id: i32, connection_id: &str) -> anyhow::Result<bool>;
+
+    async fn heartbeat(&self, user_id: i32, connection_id: &str) -> anyhow::Result<()>;
+
+    /// Removes a connection, returning whether it was the user's last one.
+    async fn disconnect(&self, user_id: i32, connection_id: &str) -> anyhow::Result<bool>;
+
+    /// The subset of `user_ids` with at least one live connection.
+    async fn online_users(&self, user_ids: &[i32]) -> anyhow::Result<HashSet<i32>>;
+}
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+
+/// Tells the connections of this process apart from other `webserver` processes.
+fn instance_id() -> &'static str {
+    static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+    INSTANCE_ID.get_or_init(|| {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards!")
+            .as_nanos();
+        format!("{}-{started}", std::process::id())
+    })
+}
+
+/// One open socket of a user, kept alive by heartbeats.
+pub struct PresenceConnection {
+    user_id: i32,
+    id: String,
+}
+
+impl PresenceConnection {
+    /// Registers the socket, returning whether the user just came online.
+    pub async fn connect(
+        presence: &dyn PresenceTracker,
+        user_id: i32,
+    ) -> anyhow::Result<(Self, bool)> {
+        let connection = Self {
+            user_id,
+            id: format!(
+                "{}:{}",
+                instance_id(),
+                NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed)
+            ),
+        };
+        let came_online = presence.connect(user_id, &connection.id).await?;
+        Ok((connection, came_online))
+    }
+
+    pub async fn heartbeat(&self, presence: &dyn PresenceTracker) -> anyhow::Result<()> {
+        presence.heartbeat(self.user_id, &self.id).await
+    }
+
+    /// Removes the socket, returning whether it was the user's last one.
+    pub async fn disconnect(self, presence: &dyn PresenceTracker) -> anyhow::Result<bool> {
+        presence.disconnect(self.user_id, &self.id).await
+    }
+}
+
+/// Publishes an online or offline transition to every chat the user is in, and
+/// to the user streams of everyone showing the user's dot in their sidebar.
+pub async fn announce(state: &AppState, user_id: i32, online: bool) {
+    let event = ServerEvent::Presence { user_id, online };
+    match ChatDatabase::get_chat_ids(&state.db, user_id).await {
+        Ok(chat_ids) => {
+            for chat_id in chat_ids {
+                if let Err(err) = state.broker.publish(Topic::Chat(chat_id), &event).await {
+                    info!("{}", err.to_string());
+                }
+            }
+        }
+        Err(err) => info!("{}", err.to_string()),
+    }
+    match ChatDatabase::get_direct_partner_ids(&state.db, user_id).await {
+        Ok(partner_ids) => {
+            for partner_id in partner_ids {
+                if let Err(err) = state.broker.publish(Topic::User(partner_id), &event).await {
+                    info!("{}", err.to_string());
+                }
+            }
+        }
+        Err(err) => info!("{}", err.to_string()),
+    }
+}
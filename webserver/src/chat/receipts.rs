@@ -0,0 +1,66 @@
+use askama::Template;
+
+use crate::chat::database::MemberSummary;
+
+#[derive(Template)]
+#[template(path = "seen-by.html")]
+pub struct SeenByTemplate {
+    pub names: Vec<String>,
+}
+
+/// Who has read the newest message of a chat, as seen by one socket.
+pub struct SeenBy {
+    viewer_id: i32,
+    latest_message_id: Option<i32>,
+    readers: Vec<(i32, String, Option<i32>)>,
+}
+
+impl SeenBy {
+    pub fn new(
+        viewer_id: i32,
+        latest_message_id: Option<i32>,
+        members: Vec<MemberSummary>,
+    ) -> Self {
+        Self {
+            viewer_id,
+            latest_message_id,
+            readers: members
+                .into_iter()
+                .map(|member| (member.user_id, member.username, member.last_read_message_id))
+                .collect(),
+        }
+    }
+
+    /// Records a new message, which nobody but its author has seen yet.
+    pub fn message(&mut self, message_id: i32, author_id: i32) {
+        self.latest_message_id = Some(message_id);
+        self.read(author_id, None, message_id);
+    }
+
+    /// Moves a reader's marker forward. Readers that joined after the socket
+    /// connected are added with the given username.
+    pub fn read(&mut self, user_id: i32, username: Option<&str>, message_id: i32) {
+        match self.readers.iter_mut().find(|(id, _, _)| *id == user_id) {
+            Some(entry) => entry.2 = entry.2.max(Some(message_id)),
+            None => {
+                if let Some(username) = username {
+                    self.readers
+                        .push((user_id, username.to_owned(), Some(message_id)));
+                }
+            }
+        }
+    }
+
+    pub fn render(&self) -> askama::Result<String> {
+        let names = match self.latest_message_id {
+            Some(latest) => self
+                .readers
+                .iter()
+                .filter(|(id, _, last_read)| *id != self.viewer_id && *last_read >= Some(latest))
+                .map(|(_, username, _)| username.clone())
+                .collect(),
+            None => vec![],
+        };
+        SeenByTemplate { names }.render()
+    }
+}
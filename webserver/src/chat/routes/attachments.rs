@@ -0,0 +1,234 @@
+use crate::chat::activity;
+use crate::chat::broker::Topic;
+use crate::chat::database::{ChatDatabase, NewAttachment};
+use crate::chat::events::ServerEvent;
+use crate::chat::routes::chat_page::forbidden;
+use crate::chat::storage::sniff_content_type;
+use crate::user::current_user::CurrentUser;
+use axum::extract::{Multipart, State};
+use axum::http::{header, StatusCode};
+use axum::response::Response;
+use axum::{debug_handler, response::IntoResponse, Form};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::AppState;
+
+/// Longest file name kept, longer ones are cut.
+const MAX_FILENAME_LENGTH: usize = 255;
+
+/// Sends a file to the chat, with the optional `message` field as its caption.
+/// Fields may come in any order, the file is held in memory, capped at the
+/// storage's size limit, until membership is checked. Like `send_message`, the
+/// message reaches the uploader over the chat stream.
+#[debug_handler]
+pub async fn upload_attachment(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    mut multipart: Multipart,
+) -> Response {
+    let mut chat_id: Option<i32> = None;
+    let mut text = String::new();
+    let mut upload: Option<(String, Vec<u8>)> = None;
+
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return err.into_response(),
+        };
+        match field.name() {
+            Some("chat_id") => {
+                chat_id = match field.text().await.ok().and_then(|id| id.parse().ok()) {
+                    Some(chat_id) => Some(chat_id),
+                    None => return (StatusCode::BAD_REQUEST, "Invalid chat_id").into_response(),
+                };
+            }
+            Some("message") => match field.text().await {
+                Ok(caption) => text = caption.trim().to_owned(),
+                Err(err) => return err.into_response(),
+            },
+            Some("file") => {
+                let filename = clean_filename(field.file_name().unwrap_or_default());
+                let mut data = Vec::new();
+                loop {
+                    match field.chunk().await {
+                        Ok(Some(chunk)) => data.extend_from_slice(&chunk),
+                        Ok(None) => break,
+                        Err(err) => return err.into_response(),
+                    }
+                    if data.len() > state.storage.max_size() {
+                        return (StatusCode::PAYLOAD_TOO_LARGE, "The file is too large")
+                            .into_response();
+                    }
+                }
+                upload = Some((filename, data));
+            }
+            _ => {}
+        }
+    }
+
+    let Some(chat_id) = chat_id else {
+        return (StatusCode::BAD_REQUEST, "Missing chat_id").into_response();
+    };
+    let member = match ChatDatabase::authorize(&state.db, chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let Some((filename, data)) = upload.filter(|(_, data)| !data.is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "Missing file").into_response();
+    };
+
+    let storage_key = Uuid::new_v4().to_string();
+    if let Err(err) = state.storage.put(&storage_key, &data).await {
+        error!("{}", err.to_string());
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    let new_attachment = NewAttachment {
+        filename,
+        content_type: sniff_content_type(&data),
+        size: data.len() as i64,
+        storage_key: storage_key.clone(),
+    };
+    let (message, attachment) = match ChatDatabase::add_attachment_message(
+        &state.db,
+        &member,
+        text,
+        new_attachment,
+    )
+    .await
+    {
+        Ok(saved) => saved,
+        Err(err) => {
+            error!("{}", err.to_string());
+            if let Err(err) = state.storage.delete(&storage_key).await {
+                error!("{}", err.to_string());
+            }
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let event = ServerEvent::Message {
+        message,
+        reply_to: None,
+        attachments: vec![attachment],
+    };
+    if let Err(err) = state
+        .broker
+        .publish(Topic::Chat(member.chat_id()), &event)
+        .await
+    {
+        error!("{}", err.to_string());
+    }
+    activity::notify_members(&state, &member).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct DownloadRequest {
+    chat_id: i32,
+    attachment_id: i32,
+}
+
+/// Serves an attachment to members of its chat. Images are shown inline, other
+/// files are downloaded.
+#[debug_handler]
+pub async fn download_attachment(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<DownloadRequest>,
+) -> Response {
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let attachment =
+        match ChatDatabase::get_attachment(&state.db, &member, request.attachment_id).await {
+            Ok(Some(attachment)) => attachment,
+            Ok(None) => {
+                return (StatusCode::NOT_FOUND, "No such attachment in this chat").into_response()
+            }
+            Err(err) => {
+                error!("{}", err.to_string());
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+    let data = match state.storage.get(&attachment.storage_key).await {
+        Ok(data) => data,
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let disposition = if attachment.content_type.starts_with("image/") {
+        "inline"
+    } else {
+        "attachment"
+    };
+    (
+        [
+            (header::CONTENT_TYPE, attachment.content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "{disposition}; filename=\"{}\"",
+                    header_filename(&attachment.filename)
+                ),
+            ),
+            // The type was sniffed on upload, browsers shouldn't second-guess it.
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned()),
+            (header::CACHE_CONTROL, "private, max-age=86400".to_owned()),
+        ],
+        data,
+    )
+        .into_response()
+}
+
+/// A file size for people, e.g. `12 KB`. Takes a reference since that is what
+/// templates pass.
+pub fn file_size(size: &i64) -> String {
+    match *size {
+        size if size < 1024 => format!("{size} B"),
+        size if size < 1024 * 1024 => format!("{} KB", size / 1024),
+        size => format!("{:.1} MB", size as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+/// The last part of the name the browser sent, without any directories.
+fn clean_filename(filename: &str) -> String {
+    let filename = filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .trim();
+    if filename.is_empty() {
+        return "file".to_owned();
+    }
+    filename.chars().take(MAX_FILENAME_LENGTH).collect()
+}
+
+/// The file name as it can appear in a quoted header value.
+fn header_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
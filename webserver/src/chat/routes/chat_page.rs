@@ -1,45 +1,288 @@
-use crate::chat::database::ChatDatabase;
+use crate::chat::activity;
+use crate::chat::broker::Topic;
+use crate::chat::database::{ChatDatabase, ChatSummary, Reactions, ReplyPreview};
+use crate::chat::events::ServerEvent;
+use crate::chat::routes::live_chat::MessageList;
+use crate::user::current_user::CurrentUser;
+use crate::user::database::UserDatabase;
 use askama::Template;
 use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
 use axum::{debug_handler, response::IntoResponse, Form};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::sync::Arc;
+use tracing::error;
 
-use crate::{entities::message, AppState, HtmlTemplate};
+use crate::{
+    entities::{attachment, message},
+    AppState, HtmlTemplate,
+};
 #[derive(Template)]
 #[template(path = "message-form.html")]
 pub struct MessageForm {
+    chat: ChatSummary,
     messages: Vec<message::Model>,
+    previews: HashMap<i32, ReplyPreview>,
+    reactions: Reactions,
+    attachments: HashMap<i32, Vec<attachment::Model>>,
     chat_id: i32,
     user_id: i32,
+    has_older: bool,
+    /// Set when the chat opens at an older message, see [`ChatDatabase::get_message_window`].
+    has_newer: bool,
+    /// Whether to stream the chat over SSE instead of a WebSocket.
+    sse: bool,
+    /// A message to scroll to, e.g. a search hit.
+    highlight: Option<i32>,
+}
+
+/// Messages added to the top or bottom of the chat as the user scrolls.
+#[derive(Template)]
+#[template(path = "message-page.html")]
+pub struct MessagePage {
+    messages: Vec<message::Model>,
+    previews: HashMap<i32, ReplyPreview>,
+    reactions: Reactions,
+    attachments: HashMap<i32, Vec<attachment::Model>>,
+    chat_id: i32,
+    user_id: i32,
+    has_older: bool,
+    has_newer: bool,
+}
+
+/// How many messages are loaded at a time, both when opening a chat and scrolling.
+const MESSAGES_PER_PAGE: u64 = 50;
+
+#[derive(Template)]
+#[template(path = "forbidden.html")]
+pub struct Forbidden;
+
+/// Response for chat routes when the current user is not a member of the chat.
+pub fn forbidden() -> Response {
+    (StatusCode::FORBIDDEN, HtmlTemplate(Forbidden)).into_response()
 }
 
 #[derive(Deserialize)]
 pub struct GetMessagesRequest {
     chat_id: i32,
-    user_id: i32,
+    #[serde(default)]
+    sse: bool,
+    message_id: Option<i32>,
 }
 
 #[debug_handler]
 pub async fn chat_page(
     State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
     Form(message): Form<GetMessagesRequest>,
-) -> impl IntoResponse {
-    let GetMessagesRequest { chat_id, user_id } = message;
-    if let Ok((messages, _)) =
-        ChatDatabase::get_chat_messages_by_id(&state.db, chat_id.into(), 1, 300).await
-    {
-        let template = MessageForm {
-            messages,
-            chat_id,
-            user_id,
-        };
+) -> Response {
+    let GetMessagesRequest {
+        chat_id,
+        sse,
+        message_id,
+    } = message;
+    let user_id = current_user.id;
+
+    let member = match ChatDatabase::authorize(&state.db, chat_id, user_id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
 
-        return HtmlTemplate(template);
+    let chat = match ChatDatabase::get_chat(&state.db, &member).await {
+        Ok(chat) => chat,
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // Opening the chat reads everything in it.
+    match ChatDatabase::mark_chat_read(&state.db, &member).await {
+        Ok(Some(message_id)) => {
+            if let Ok(Some(user)) = UserDatabase::get_user_by_id(&state.db, user_id).await {
+                let event = ServerEvent::Read {
+                    user_id,
+                    username: user.username,
+                    message_id,
+                };
+                if let Err(err) = state.broker.publish(Topic::Chat(chat_id), &event).await {
+                    error!("{}", err.to_string());
+                }
+            }
+            activity::notify_users(&state, chat_id, &[user_id]).await;
+        }
+        Ok(None) => {}
+        Err(err) => error!("{}", err.to_string()),
     }
+
+    // Jumping to a message opens the chat around it instead of at the newest page.
+    let window = match message_id {
+        Some(message_id) => match ChatDatabase::get_message_window(
+            &state.db,
+            &member,
+            message_id,
+            MESSAGES_PER_PAGE / 2,
+        )
+        .await
+        {
+            Ok(window) => window,
+            Err(err) => {
+                error!("{}", err.to_string());
+                None
+            }
+        },
+        None => None,
+    };
+    let (messages, has_older, has_newer, highlight) = match window {
+        Some((messages, has_older, has_newer)) => (messages, has_older, has_newer, message_id),
+        None => {
+            match ChatDatabase::get_chat_messages_before(
+                &state.db,
+                &member,
+                None,
+                MESSAGES_PER_PAGE,
+            )
+            .await
+            {
+                Ok((messages, has_older)) => (messages, has_older, false, None),
+                Err(err) => {
+                    error!("{}", err.to_string());
+                    (vec![], false, false, None)
+                }
+            }
+        }
+    };
+    let MessageList {
+        messages,
+        previews,
+        reactions,
+        attachments,
+        ..
+    } = MessageList::load(&state, &member, messages, user_id).await;
     HtmlTemplate(MessageForm {
-        messages: vec![],
+        chat,
+        messages,
+        previews,
+        reactions,
+        attachments,
         chat_id,
         user_id,
+        has_older,
+        has_newer,
+        sse,
+        highlight,
     })
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct OlderMessagesRequest {
+    chat_id: i32,
+    before_timestamp: i32,
+    before_id: i32,
+}
+
+#[debug_handler]
+pub async fn older_messages(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<OlderMessagesRequest>,
+) -> Response {
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let before = Some((request.before_timestamp, request.before_id));
+    match ChatDatabase::get_chat_messages_before(&state.db, &member, before, MESSAGES_PER_PAGE)
+        .await
+    {
+        Ok((messages, has_older)) => {
+            let MessageList {
+                messages,
+                previews,
+                reactions,
+                attachments,
+                ..
+            } = MessageList::load(&state, &member, messages, current_user.id).await;
+            HtmlTemplate(MessagePage {
+                messages,
+                previews,
+                reactions,
+                attachments,
+                chat_id: request.chat_id,
+                user_id: current_user.id,
+                has_older,
+                has_newer: false,
+            })
+            .into_response()
+        }
+        Err(err) => {
+            error!("{}", err.to_string());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewerMessagesRequest {
+    chat_id: i32,
+    after_timestamp: i32,
+    after_id: i32,
+}
+
+/// The next page below a chat that was opened at an older message.
+#[debug_handler]
+pub async fn newer_messages(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<NewerMessagesRequest>,
+) -> Response {
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let after = (request.after_timestamp, request.after_id);
+    match ChatDatabase::get_chat_messages_newer(&state.db, &member, after, MESSAGES_PER_PAGE).await
+    {
+        Ok((messages, has_newer)) => {
+            let MessageList {
+                messages,
+                previews,
+                reactions,
+                attachments,
+                ..
+            } = MessageList::load(&state, &member, messages, current_user.id).await;
+            HtmlTemplate(MessagePage {
+                messages,
+                previews,
+                reactions,
+                attachments,
+                chat_id: request.chat_id,
+                user_id: current_user.id,
+                has_older: false,
+                has_newer,
+            })
+            .into_response()
+        }
+        Err(err) => {
+            error!("{}", err.to_string());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
@@ -0,0 +1,155 @@
+use crate::chat::activity;
+use crate::chat::database::ChatDatabase;
+use crate::chat::routes::get_chats::{chats_template, ChatsTemplate};
+use crate::user::current_user::CurrentUser;
+use crate::user::database::UserDatabase;
+use axum::extract::State;
+use axum::{debug_handler, response::IntoResponse, Form};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{AppState, HtmlTemplate};
+
+/// Both forms post to `/chats`, told apart by their `kind` field.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum CreateChatRequest {
+    Direct(CreateDirectChatRequest),
+    Group(CreateGroupChatRequest),
+}
+
+#[debug_handler]
+pub async fn create_chat(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<CreateChatRequest>,
+) -> impl IntoResponse {
+    match request {
+        CreateChatRequest::Direct(request) => {
+            create_direct_chat(&state, current_user, request).await
+        }
+        CreateChatRequest::Group(request) => create_group_chat(&state, current_user, request).await,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateDirectChatRequest {
+    username: String,
+}
+
+async fn create_direct_chat(
+    state: &AppState,
+    current_user: CurrentUser,
+    request: CreateDirectChatRequest,
+) -> HtmlTemplate<ChatsTemplate> {
+    let username = request.username.trim().to_owned();
+    let other_user = match UserDatabase::get_user_by_username(&state.db, username.clone()).await {
+        Ok(Some(user)) if user.id != current_user.id => user,
+        Ok(Some(_)) => {
+            let error = "You can't start a chat with yourself".to_owned();
+            return chats_template(state, current_user.id, None, Some(error)).await;
+        }
+        Ok(None) => {
+            let error = format!("No user named {username}");
+            return chats_template(state, current_user.id, None, Some(error)).await;
+        }
+        Err(err) => {
+            error!("{}", err.to_string());
+            let error = "Failed to start the chat".to_owned();
+            return chats_template(state, current_user.id, None, Some(error)).await;
+        }
+    };
+
+    match ChatDatabase::get_or_create_direct_chat(&state.db, current_user.id, other_user.id).await {
+        Ok(chat) => {
+            activity::notify_users(state, chat.id, &[other_user.id]).await;
+            chats_template(state, current_user.id, Some(chat.id), None).await
+        }
+        Err(err) => {
+            error!("{}", err.to_string());
+            let error = "Failed to start the chat".to_owned();
+            chats_template(state, current_user.id, None, Some(error)).await
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateGroupChatRequest {
+    name: String,
+    topic: String,
+    avatar_url: String,
+    /// Comma separated usernames to add next to the creator.
+    members: String,
+}
+
+async fn create_group_chat(
+    state: &AppState,
+    current_user: CurrentUser,
+    request: CreateGroupChatRequest,
+) -> HtmlTemplate<ChatsTemplate> {
+    let name = request.name.trim().to_owned();
+    if name.is_empty() {
+        let error = "A group needs a name".to_owned();
+        return chats_template(state, current_user.id, None, Some(error)).await;
+    }
+
+    let topic = Some(request.topic.trim().to_owned()).filter(|topic| !topic.is_empty());
+    let avatar_url =
+        Some(request.avatar_url.trim().to_owned()).filter(|avatar_url| !avatar_url.is_empty());
+    if let Some(avatar_url) = &avatar_url {
+        if !avatar_url.starts_with("https://") && !avatar_url.starts_with("http://") {
+            let error = "The avatar must be an http(s) URL".to_owned();
+            return chats_template(state, current_user.id, None, Some(error)).await;
+        }
+    }
+
+    let usernames: Vec<String> = request
+        .members
+        .split(',')
+        .map(|username| username.trim().to_owned())
+        .filter(|username| !username.is_empty())
+        .collect();
+
+    let users = match UserDatabase::get_users_by_usernames(&state.db, usernames.clone()).await {
+        Ok(users) => users,
+        Err(err) => {
+            error!("{}", err.to_string());
+            let error = "Failed to create the group".to_owned();
+            return chats_template(state, current_user.id, None, Some(error)).await;
+        }
+    };
+
+    let missing: Vec<&str> = usernames
+        .iter()
+        .filter(|username| !users.iter().any(|user| &user.username == *username))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        let error = format!("No users named {}", missing.join(", "));
+        return chats_template(state, current_user.id, None, Some(error)).await;
+    }
+
+    let member_ids: Vec<i32> = users.iter().map(|user| user.id).collect();
+
+    match ChatDatabase::create_group_chat(
+        &state.db,
+        current_user.id,
+        name,
+        topic,
+        avatar_url,
+        member_ids.clone(),
+    )
+    .await
+    {
+        Ok(chat) => {
+            activity::notify_users(state, chat.id, &member_ids).await;
+            chats_template(state, current_user.id, Some(chat.id), None).await
+        }
+        Err(err) => {
+            error!("{}", err.to_string());
+            let error = "Failed to create the group".to_owned();
+            chats_template(state, current_user.id, None, Some(error)).await
+        }
+    }
+}
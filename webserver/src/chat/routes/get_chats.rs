@@ -1,41 +1,93 @@
-use crate::chat::database::ChatDatabase;
+use crate::chat::database::{ChatDatabase, ChatSummary};
+use crate::user::current_user::CurrentUser;
 use askama::Template;
-use axum::extract::{State};
-use axum::{debug_handler, response::IntoResponse, Form};
-
-
-
-use serde::{Deserialize};
+use axum::extract::State;
+use axum::{debug_handler, response::IntoResponse};
+use sea_orm::DbErr;
+use std::collections::HashSet;
 use std::sync::Arc;
-
+use tracing::error;
 
 use crate::{AppState, HtmlTemplate};
 
-#[derive(Deserialize)]
-pub struct GetChatsRequest {
-    user_id: i64,
-}
 #[derive(Template)]
 #[template(path = "chats.html")]
 pub struct ChatsTemplate {
-    chats: Vec<crate::entities::chat::Model>,
+    chats: Vec<ChatSummary>,
+    open_chat_id: Option<i32>,
+    error: Option<String>,
+    online_users: HashSet<i32>,
+}
+
+/// Replaces a chat's row in the sidebar, pushed over the user stream. The row is
+/// re-inserted rather than swapped so new chats show up too, and its CSS order
+/// puts it in place. Users that left the chat only get the row removed.
+#[derive(Template)]
+#[template(path = "chat-row-update.html")]
+pub struct ChatRowUpdate {
+    chat_id: i32,
+    chat: Option<ChatSummary>,
+    online_users: HashSet<i32>,
+}
+
+impl ChatRowUpdate {
+    pub async fn new(state: &AppState, chat_id: i32, user_id: i32) -> Result<Self, DbErr> {
+        let Some(member) = ChatDatabase::authorize(&state.db, chat_id, user_id).await? else {
+            return Ok(Self {
+                chat_id,
+                chat: None,
+                online_users: HashSet::new(),
+            });
+        };
+        let chat = ChatDatabase::get_chat(&state.db, &member).await?;
+        let online_users =
+            online_partners(state, &chat.partner_id.into_iter().collect::<Vec<_>>()).await;
+        Ok(Self {
+            chat_id,
+            chat: Some(chat),
+            online_users,
+        })
+    }
+}
+
+async fn online_partners(state: &AppState, partner_ids: &[i32]) -> HashSet<i32> {
+    state
+        .presence
+        .online_users(partner_ids)
+        .await
+        .unwrap_or_else(|err| {
+            error!("{}", err.to_string());
+            HashSet::new()
+        })
+}
+
+/// Renders the sidebar for the user, optionally opening a chat in the chat box.
+pub async fn chats_template(
+    state: &AppState,
     user_id: i32,
+    open_chat_id: Option<i32>,
+    error: Option<String>,
+) -> HtmlTemplate<ChatsTemplate> {
+    let chats = ChatDatabase::get_chats_by_user_id(&state.db, user_id.into(), 1, 100)
+        .await
+        .map(|(chats, _)| chats)
+        .unwrap_or_default();
+
+    let partner_ids: Vec<i32> = chats.iter().filter_map(|chat| chat.partner_id).collect();
+    let online_users = online_partners(state, &partner_ids).await;
+
+    HtmlTemplate(ChatsTemplate {
+        chats,
+        open_chat_id,
+        error,
+        online_users,
+    })
 }
 
 #[debug_handler]
 pub async fn get_chats(
     State(state): State<Arc<AppState>>,
-    Form(request): Form<GetChatsRequest>,
+    current_user: CurrentUser,
 ) -> impl IntoResponse {
-    let chats = ChatDatabase::get_chats_by_user_id(&state.db, request.user_id, 1, 100).await;
-    if let Ok((chats, _)) = chats {
-        return HtmlTemplate(ChatsTemplate {
-            chats,
-            user_id: request.user_id as i32,
-        });
-    }
-    HtmlTemplate(ChatsTemplate {
-        chats: vec![],
-        user_id: request.user_id as i32,
-    })
+    chats_template(&state, current_user.id, None, None).await
 }
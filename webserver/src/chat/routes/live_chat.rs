@@ -1,119 +1,823 @@
-use crate::chat::database::ChatDatabase;
+use crate::chat::activity;
+use crate::chat::broker::Topic;
+use crate::chat::database::{ChatDatabase, ChatMember, Reactions, ReplyPreview};
+use crate::chat::events::{ClientEvent, ServerEvent};
+use crate::chat::presence::{self, PresenceConnection, HEARTBEAT_INTERVAL};
+use crate::chat::receipts::SeenBy;
+use crate::chat::typing::TypingUsers;
+use crate::user::current_user::CurrentUser;
+use crate::user::database::UserDatabase;
 use askama::Template;
-use axum::extract::ws::Message;
+use axum::extract::ws::{CloseFrame, Message};
 use axum::extract::Query;
 use axum::extract::{ws::WebSocket, State, WebSocketUpgrade};
 use axum::{debug_handler, response::IntoResponse};
 use futures::{sink::SinkExt, stream::StreamExt};
-use redis::Commands;
+use sea_orm::DbErr;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 use tracing::info;
 
-use crate::{entities::message, AppState};
+use crate::{
+    entities::{attachment, message},
+    AppState,
+};
 
 #[derive(Deserialize)]
 pub struct ReceiveMessagesRequest {
     chat_id: i32,
-    user_id: i32,
 }
 
 #[debug_handler]
 pub async fn live_chat_websocket(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
     Query(message): Query<ReceiveMessagesRequest>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| live_chat(socket, state, message.user_id, message.chat_id))
+    ws.on_upgrade(move |socket| live_chat(socket, state, current_user.id, message.chat_id))
 }
 
 #[derive(Template, Deserialize, Serialize)]
 #[template(path = "message-list.html")]
 pub struct MessageList {
-    messages: Vec<message::Model>,
-    user_id: i32,
+    pub messages: Vec<message::Model>,
+    /// The messages replied to, by id, see [`ChatDatabase::get_reply_previews`].
+    pub previews: HashMap<i32, ReplyPreview>,
+    pub reactions: Reactions,
+    /// The files of each message, by message id.
+    pub attachments: HashMap<i32, Vec<attachment::Model>>,
+    pub user_id: i32,
 }
-#[derive(Deserialize)]
-pub struct MessageRequest {
-    message: String,
-    user_id: i32,
-    chat_id: i32,
+
+impl MessageList {
+    /// The messages with everything shown alongside them. Whatever fails to load
+    /// is left out, the messages still render without it.
+    pub async fn load(
+        state: &AppState,
+        member: &ChatMember,
+        messages: Vec<message::Model>,
+        user_id: i32,
+    ) -> Self {
+        let message_ids: Vec<i32> = messages.iter().map(|message| message.id).collect();
+        let previews = ChatDatabase::get_reply_previews(&state.db, member, &messages)
+            .await
+            .unwrap_or_else(|err| {
+                info!("{}", err.to_string());
+                HashMap::new()
+            });
+        let reactions = ChatDatabase::get_reactions(&state.db, member, &message_ids)
+            .await
+            .unwrap_or_else(|err| {
+                info!("{}", err.to_string());
+                Reactions::default()
+            });
+        let attachments = ChatDatabase::get_attachments(&state.db, member, &message_ids)
+            .await
+            .unwrap_or_else(|err| {
+                info!("{}", err.to_string());
+                HashMap::new()
+            });
+        Self {
+            messages,
+            previews,
+            reactions,
+            attachments,
+            user_id,
+        }
+    }
+}
+
+/// Edited or deleted messages, rendered to replace the element already on the page.
+#[derive(Template)]
+#[template(path = "message-update.html")]
+pub struct MessageUpdate {
+    pub messages: Vec<message::Model>,
+    pub previews: HashMap<i32, ReplyPreview>,
+    pub reactions: Reactions,
+    pub attachments: HashMap<i32, Vec<attachment::Model>>,
+    pub user_id: i32,
+    /// Whether to swap out-of-band, as needed when pushed over the socket.
+    pub oob: bool,
+}
+
+#[derive(Template)]
+#[template(path = "presence.html")]
+pub struct Presence {
+    pub user_id: i32,
+    pub online: bool,
+}
+
+#[derive(Template)]
+#[template(path = "chat-error.html")]
+pub struct ChatError {
+    pub error: String,
+}
+
+/// Close code sent when the user is not a member of the requested chat.
+const CLOSE_FORBIDDEN: u16 = 4003;
+
+fn close_forbidden() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: CLOSE_FORBIDDEN,
+        reason: "not a member of this chat".into(),
+    }))
+}
+
+/// What the socket should do after handling a client event.
+enum Outcome {
+    Continue,
+    /// Answer only this socket, e.g. with an error.
+    Reply(ServerEvent),
+    /// Replay what the client missed, see [`ClientEvent::Resume`].
+    Resume(Resume),
+    Close,
+}
+
+/// A client's [`ClientEvent::Resume`], passed on to the task streaming to it.
+struct Resume {
+    after: Option<i32>,
+    window: bool,
+    cursor: Option<String>,
+}
+
+/// Messages read at a time when replaying what a reconnecting client missed.
+const BACKFILL_PAGE: u64 = 300;
+
+/// How long a socket waits for the client's resume event before it stops
+/// remembering what it streamed. Pages send it as soon as the socket opens.
+const RESUME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Every message after `after`, read a page at a time until caught up, so even
+/// a long absence leaves no gap.
+pub async fn missed_messages(
+    state: &AppState,
+    member: &ChatMember,
+    mut after: i32,
+) -> Result<Vec<message::Model>, DbErr> {
+    let mut missed = vec![];
+    loop {
+        let page =
+            ChatDatabase::get_chat_messages_after(&state.db, member, after, BACKFILL_PAGE).await?;
+        let caught_up = (page.len() as u64) < BACKFILL_PAGE;
+        if let Some(last) = page.last() {
+            after = last.id;
+        }
+        missed.extend(page);
+        if caught_up {
+            return Ok(missed);
+        }
+    }
+}
+
+/// Keeps the replay of missed messages and the live stream from overlapping.
+/// The subscription starts before the replay is read from the database, so a
+/// message can show up in both, but never in neither.
+struct Backfill {
+    /// Messages streamed before the client said where it left off, `None` once
+    /// it has or took longer than [`RESUME_TIMEOUT`].
+    streamed: Option<HashSet<i32>>,
+    /// Replayed messages that may still come by live.
+    replayed: HashSet<i32>,
+    opened: Instant,
+}
+
+impl Backfill {
+    fn new() -> Self {
+        Self {
+            streamed: Some(HashSet::new()),
+            replayed: HashSet::new(),
+            opened: Instant::now(),
+        }
+    }
+
+    /// Stops waiting for a resume that is overdue, a later one replays nothing.
+    fn expire(&mut self) {
+        if self.streamed.is_some() && self.opened.elapsed() >= RESUME_TIMEOUT {
+            self.streamed = None;
+        }
+    }
+
+    /// Whether a message from the live stream still has to be sent.
+    fn live(&mut self, message_id: i32) -> bool {
+        self.expire();
+        let replayed = self.replayed.remove(&message_id);
+        // Messages arrive in order, older replayed ones were from before the subscription.
+        self.replayed.retain(|id| *id > message_id);
+        if replayed {
+            return false;
+        }
+        if let Some(streamed) = self.streamed.as_mut() {
+            streamed.insert(message_id);
+        }
+        true
+    }
+
+    /// Drops what was already streamed from the replay. Only the first resume counts.
+    fn replay(&mut self, messages: Vec<message::Model>) -> Vec<message::Model> {
+        self.expire();
+        let Some(streamed) = self.streamed.take() else {
+            return vec![];
+        };
+        let messages: Vec<_> = messages
+            .into_iter()
+            .filter(|message| !streamed.contains(&message.id))
+            .collect();
+        self.replayed = messages.iter().map(|message| message.id).collect();
+        messages
+    }
+}
+
+/// Why the sender task woke up.
+enum Wake {
+    Event(ServerEvent),
+    Resume(Resume),
+    Tick,
 }
 
-async fn live_chat(stream: WebSocket, state: Arc<AppState>, user_id: i32, chat_id: i32) {
+#[derive(Template)]
+#[template(path = "stream-cursor.html")]
+struct StreamCursor<'a> {
+    position: &'a str,
+}
+
+/// Events from the broker's log worth replaying. Messages come from the database
+/// instead, and typing notices are stale by the time the client is back.
+fn replays(event: &ServerEvent) -> bool {
+    !matches!(
+        event,
+        ServerEvent::Message { .. } | ServerEvent::Typing { .. }
+    )
+}
+
+async fn live_chat(mut stream: WebSocket, state: Arc<AppState>, user_id: i32, chat_id: i32) {
+    let member = match ChatDatabase::authorize(&state.db, chat_id, user_id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => {
+            let _ = stream.send(close_forbidden()).await;
+            return;
+        }
+        Err(err) => {
+            info!("{}", err.to_string());
+            return;
+        }
+    };
+    // Looked up once so typing notices can be sent without touching Postgres.
+    let username = match UserDatabase::get_user_by_id(&state.db, user_id).await {
+        Ok(Some(user)) => user.username,
+        Ok(None) => return,
+        Err(err) => {
+            info!("{}", err.to_string());
+            return;
+        }
+    };
+
+    let seen_by = match (
+        ChatDatabase::get_members(&state.db, &member).await,
+        ChatDatabase::get_latest_message_id(&state.db, &member).await,
+    ) {
+        (Ok(members), Ok(latest_message_id)) => SeenBy::new(user_id, latest_message_id, members),
+        (Err(err), _) | (_, Err(err)) => {
+            info!("{}", err.to_string());
+            return;
+        }
+    };
+
+    let presence = match PresenceConnection::connect(state.presence.as_ref(), user_id).await {
+        Ok((presence, came_online)) => {
+            if came_online {
+                presence::announce(&state, user_id, true).await;
+            }
+            Some(presence)
+        }
+        Err(err) => {
+            info!("{}", err.to_string());
+            None
+        }
+    };
+    let presence = Arc::new(presence);
+
     // By splitting, we can send and receive at the same time.
     let (mut sender, mut receiver) = stream.split();
 
+    // Both the Redis subscription and replies to this client go through one writer.
+    let (outgoing, mut outgoing_rx) = mpsc::channel::<Message>(32);
+    let mut writer_task = tokio::spawn(async move {
+        while let Some(message) = outgoing_rx.recv().await {
+            let is_close = matches!(message, Message::Close(_));
+            if sender.send(message).await.is_err() || is_close {
+                break;
+            }
+        }
+    });
+
     let sender_state = state.clone();
+    let sender_outgoing = outgoing.clone();
+    let username_for_reads = username.clone();
+    let sender_presence = presence.clone();
+    let (resume, mut resume_rx) = mpsc::channel::<Resume>(1);
     let mut sender_task = tokio::spawn(async move {
-        let client = &sender_state.redis;
-        let mut redis_conn = client
-            .get_connection()
-            .expect("Failed getting redis connection");
-        let mut pubsub = redis_conn.as_pubsub();
-        pubsub.subscribe(format!("chat:{chat_id}")).unwrap();
+        let mut subscription = match sender_state.broker.subscribe(Topic::Chat(chat_id)).await {
+            Ok(subscription) => subscription,
+            Err(err) => {
+                info!("{}", err.to_string());
+                return;
+            }
+        };
+        // Wake up regularly so typing notices expire even when the chat is quiet.
+        let mut ticks = tokio::time::interval(Duration::from_secs(1));
+        let mut typing = TypingUsers::default();
+        let mut seen_by = seen_by;
+        let mut last_heartbeat = Instant::now();
+        let mut backfill = Backfill::new();
+        // Replayed broker events, handled like live ones before waiting for more.
+        let mut missed = VecDeque::new();
+        let mut cursor = None;
+        if !send_seen_by(&seen_by, &sender_outgoing).await {
+            return;
+        }
 
         loop {
-            let msg = pubsub.get_message().unwrap();
-            let payload: String = msg.get_payload().unwrap();
-            let res: Result<message::Model, _> = serde_json::from_str(&payload);
-            let msg = if let Ok(new_message) = res {
-                let template = MessageList {
-                    user_id,
-                    messages: vec![new_message],
-                };
-
-                template
+            // Keep the client's cursor on the last broker event it was sent.
+            let position = subscription.position().or(subscription.start());
+            if position.is_some() && position != cursor.as_deref() && missed.is_empty() {
+                cursor = position.map(str::to_owned);
+                if !send_cursor(cursor.as_deref().unwrap_or_default(), &sender_outgoing).await {
+                    break;
+                }
+            }
+
+            let wake = if let Some(event) = missed.pop_front() {
+                Wake::Event(event)
             } else {
-                MessageList {
-                    messages: vec![],
-                    user_id,
+                tokio::select! {
+                    event = subscription.recv() => match event {
+                        Some(event) => Wake::Event(event),
+                        None => break,
+                    },
+                    Some(resume) = resume_rx.recv() => Wake::Resume(resume),
+                    _ = ticks.tick() => Wake::Tick,
                 }
             };
-            if sender
-                .send(Message::Text(msg.render().unwrap()))
-                .await
-                .is_err()
-            {
+            if typing.expire() && !send_typing(&typing, &sender_outgoing).await {
                 break;
             }
+            if let Some(presence) = sender_presence.as_ref() {
+                if last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL {
+                    if let Err(err) = presence.heartbeat(sender_state.presence.as_ref()).await {
+                        info!("{}", err.to_string());
+                    }
+                    last_heartbeat = Instant::now();
+                }
+            }
+            let event = match wake {
+                Wake::Event(event) => event,
+                Wake::Resume(resume) => {
+                    if let Some(after) = &resume.cursor {
+                        match sender_state.broker.replay(&subscription, after).await {
+                            Ok(events) => missed.extend(events.into_iter().filter(replays)),
+                            Err(err) => info!("{}", err.to_string()),
+                        }
+                    }
+                    // No messages are replayed into an older window, but the live
+                    // stream stops being tracked.
+                    if resume.window {
+                        backfill.replay(vec![]);
+                        continue;
+                    }
+                    let after = resume.after.unwrap_or(0);
+                    let messages = match missed_messages(&sender_state, &member, after).await {
+                        Ok(messages) => backfill.replay(messages),
+                        Err(err) => {
+                            info!("{}", err.to_string());
+                            continue;
+                        }
+                    };
+                    let Some(last) = messages.last() else {
+                        continue;
+                    };
+                    let (last_id, last_author_id) = (last.id, last.user_id);
+                    let list = MessageList::load(&sender_state, &member, messages, user_id).await;
+                    let html = match list.render() {
+                        Ok(html) => html,
+                        Err(err) => {
+                            info!("{}", err.to_string());
+                            continue;
+                        }
+                    };
+                    if sender_outgoing.send(Message::Text(html)).await.is_err() {
+                        break;
+                    }
+                    seen_by.message(last_id, last_author_id);
+                    publish_read(&sender_state, &member, &username_for_reads, last_id).await;
+                    if !send_seen_by(&seen_by, &sender_outgoing).await {
+                        break;
+                    }
+                    continue;
+                }
+                Wake::Tick => {
+                    backfill.expire();
+                    continue;
+                }
+            };
+
+            if let ServerEvent::Message { message, .. } = &event {
+                if !backfill.live(message.id) {
+                    continue;
+                }
+            }
+
+            match &event {
+                ServerEvent::Typing {
+                    user_id: typist,
+                    username,
+                } => {
+                    if *typist != user_id {
+                        typing.start(*typist, username.clone());
+                        if !send_typing(&typing, &sender_outgoing).await {
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                ServerEvent::Read {
+                    user_id: reader,
+                    username,
+                    message_id,
+                } => {
+                    seen_by.read(*reader, Some(username), *message_id);
+                    if !send_seen_by(&seen_by, &sender_outgoing).await {
+                        break;
+                    }
+                    continue;
+                }
+                // A message replaces the notice of whoever was typing it.
+                ServerEvent::Message { message, .. }
+                    if typing.stop(message.user_id)
+                        && !send_typing(&typing, &sender_outgoing).await =>
+                {
+                    break;
+                }
+                _ => {}
+            }
+
+            // Membership changes are announced with a system message, stop streaming
+            // to users that were removed from the chat.
+            if let ServerEvent::Message { message, .. } = &event {
+                if message.is_system
+                    && !matches!(
+                        ChatDatabase::authorize(&sender_state.db, chat_id, user_id).await,
+                        Ok(Some(_))
+                    )
+                {
+                    let _ = sender_outgoing.send(close_forbidden()).await;
+                    break;
+                }
+            }
+
+            let new_message = match &event {
+                ServerEvent::Message { message, .. } => Some((message.id, message.user_id)),
+                _ => None,
+            };
+            let html = match event.render(user_id) {
+                Ok(html) => html,
+                Err(err) => {
+                    info!("{}", err.to_string());
+                    continue;
+                }
+            };
+            if sender_outgoing.send(Message::Text(html)).await.is_err() {
+                break;
+            }
+
+            // The message reached an open chat, so it counts as read.
+            if let Some((message_id, author_id)) = new_message {
+                seen_by.message(message_id, author_id);
+                if author_id != user_id {
+                    publish_read(&sender_state, &member, &username_for_reads, message_id).await;
+                }
+                if !send_seen_by(&seen_by, &sender_outgoing).await {
+                    break;
+                }
+            }
         }
     });
 
     let receiver_state = state.clone();
     let mut receiver_task = tokio::spawn(async move {
-        while let Some(Ok(Message::Text(text))) = receiver.next().await {
-            let message: MessageRequest = serde_json::from_str(&text).unwrap();
-
-            let res = ChatDatabase::add_message(
-                &receiver_state.db,
-                message.message.clone(),
-                message.user_id,
-                message.chat_id,
-            )
-            .await;
-            if let Err(err) = res {
-                info!("{}", err.to_string());
-                break;
-            }
+        while let Some(Ok(frame)) = receiver.next().await {
+            let text = match frame {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
 
-            let mut redis_conn = state
-                .redis
-                .get_connection()
-                .expect("Failed getting redis connection");
+            let outcome = match serde_json::from_str::<ClientEvent>(&text) {
+                Ok(event) => {
+                    handle_client_event(&receiver_state, user_id, &username, chat_id, event).await
+                }
+                Err(err) => Outcome::Reply(ServerEvent::Error {
+                    error: format!("Unrecognized event: {err}"),
+                }),
+            };
 
-            if let Ok(new_message) = res {
-                let _ = redis_conn.publish::<String, String, String>(
-                    format!("chat:{}", message.chat_id),
-                    serde_json::to_string(&new_message).unwrap(),
-                );
+            let reply = match outcome {
+                Outcome::Continue => continue,
+                Outcome::Resume(request) => {
+                    if resume.send(request).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                Outcome::Reply(event) => match event.render(user_id) {
+                    Ok(html) => Message::Text(html),
+                    Err(err) => {
+                        info!("{}", err.to_string());
+                        continue;
+                    }
+                },
+                Outcome::Close => close_forbidden(),
+            };
+            let is_close = matches!(reply, Message::Close(_));
+            if outgoing.send(reply).await.is_err() || is_close {
+                break;
             }
         }
     });
 
-    // If any one of the tasks run to completion, we abort the other.
+    // If any one of the tasks run to completion, we abort the others.
     tokio::select! {
-        _ = (&mut sender_task) => sender_task.abort(),
-        _ = (&mut receiver_task) => receiver_task.abort(),
+        _ = (&mut writer_task) => {},
+        _ = (&mut sender_task) => {},
+        _ = (&mut receiver_task) => {},
+    };
+    sender_task.abort();
+    receiver_task.abort();
+    // Let the writer flush a pending close frame before it is dropped.
+    let _ = tokio::time::timeout(Duration::from_secs(1), &mut writer_task).await;
+    writer_task.abort();
+
+    // The aborted sender task may still hold its clone until it is dropped.
+    let _ = sender_task.await;
+    if let Some(presence) = Arc::into_inner(presence).flatten() {
+        match presence.disconnect(state.presence.as_ref()).await {
+            Ok(true) => presence::announce(&state, user_id, false).await,
+            Ok(false) => {}
+            Err(err) => info!("{}", err.to_string()),
+        }
+    }
+}
+
+async fn send_typing(typing: &TypingUsers, outgoing: &mpsc::Sender<Message>) -> bool {
+    match typing.render() {
+        Ok(html) => outgoing.send(Message::Text(html)).await.is_ok(),
+        Err(err) => {
+            info!("{}", err.to_string());
+            true
+        }
+    }
+}
+
+async fn send_cursor(position: &str, outgoing: &mpsc::Sender<Message>) -> bool {
+    match (StreamCursor { position }).render() {
+        Ok(html) => outgoing.send(Message::Text(html)).await.is_ok(),
+        Err(err) => {
+            info!("{}", err.to_string());
+            true
+        }
+    }
+}
+
+async fn send_seen_by(seen_by: &SeenBy, outgoing: &mpsc::Sender<Message>) -> bool {
+    match seen_by.render() {
+        Ok(html) => outgoing.send(Message::Text(html)).await.is_ok(),
+        Err(err) => {
+            info!("{}", err.to_string());
+            true
+        }
+    }
+}
+
+/// Moves the member's read marker to `message_id` and lets the chat know if it moved.
+pub async fn publish_read(state: &AppState, member: &ChatMember, username: &str, message_id: i32) {
+    match ChatDatabase::mark_read(&state.db, member, message_id).await {
+        Ok(true) => {
+            let event = ServerEvent::Read {
+                user_id: member.user_id(),
+                username: username.to_owned(),
+                message_id,
+            };
+            if let Err(err) = state
+                .broker
+                .publish(Topic::Chat(member.chat_id()), &event)
+                .await
+            {
+                info!("{}", err.to_string());
+            }
+            // Clears the unread badge in the reader's sidebar.
+            activity::notify_users(state, member.chat_id(), &[member.user_id()]).await;
+        }
+        Ok(false) => {}
+        Err(err) => info!("{}", err.to_string()),
+    }
+}
+
+/// An edited message with everything it shows besides its text, since the
+/// whole element is swapped.
+pub async fn edit_event(
+    state: &AppState,
+    member: &ChatMember,
+    message: message::Model,
+) -> Result<ServerEvent, DbErr> {
+    let reply_to = ChatDatabase::get_reply_preview(&state.db, member, &message).await?;
+    let reactions = ChatDatabase::get_reactions(&state.db, member, &[message.id])
+        .await?
+        .of(&message.id)
+        .to_vec();
+    let attachments = ChatDatabase::get_attachments(&state.db, member, &[message.id])
+        .await?
+        .remove(&message.id)
+        .unwrap_or_default();
+    Ok(ServerEvent::Edit {
+        message,
+        reply_to,
+        reactions,
+        attachments,
+    })
+}
+
+async fn handle_client_event(
+    state: &AppState,
+    user_id: i32,
+    username: &str,
+    chat_id: i32,
+    event: ClientEvent,
+) -> Outcome {
+    // Typing notices are too frequent to hit Postgres for, membership was checked on
+    // connect and removed members get disconnected by the membership system message.
+    if let ClientEvent::Resume {
+        after,
+        window,
+        cursor,
+    } = event
+    {
+        return Outcome::Resume(Resume {
+            after,
+            window,
+            cursor,
+        });
+    }
+    if let ClientEvent::Typing = event {
+        let event = ServerEvent::Typing {
+            user_id,
+            username: username.to_owned(),
+        };
+        if let Err(err) = state.broker.publish(Topic::Chat(chat_id), &event).await {
+            info!("{}", err.to_string());
+        }
+        return Outcome::Continue;
+    }
+
+    // Membership is checked again so a user removed from the chat can't keep posting.
+    let member = match ChatDatabase::authorize(&state.db, chat_id, user_id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return Outcome::Close,
+        Err(err) => {
+            info!("{}", err.to_string());
+            return Outcome::Reply(ServerEvent::Error {
+                error: "Something went wrong, please try again".to_owned(),
+            });
+        }
     };
+
+    let published = match event {
+        ClientEvent::Send { message } => {
+            let message = message.trim().to_owned();
+            if message.is_empty() {
+                return Outcome::Continue;
+            }
+            match ChatDatabase::add_message(&state.db, &member, message, None).await {
+                Ok(message) => ChatDatabase::get_reply_preview(&state.db, &member, &message)
+                    .await
+                    .map(|reply_to| {
+                        Some(ServerEvent::Message {
+                            message,
+                            reply_to,
+                            attachments: vec![],
+                        })
+                    }),
+                Err(err) => Err(err),
+            }
+        }
+        ClientEvent::Edit { message_id, text } => {
+            let text = text.trim().to_owned();
+            if text.is_empty() {
+                return Outcome::Reply(ServerEvent::Error {
+                    error: "A message can't be empty".to_owned(),
+                });
+            }
+            match ChatDatabase::edit_message(&state.db, &member, message_id, text).await {
+                Ok(Some(message)) => edit_event(state, &member, message).await.map(Some),
+                other => other.map(|_| None),
+            }
+        }
+        ClientEvent::Delete { message_id } => {
+            ChatDatabase::delete_message(&state.db, &member, message_id)
+                .await
+                .map(|message| message.map(ServerEvent::delete))
+        }
+        ClientEvent::Read => {
+            match ChatDatabase::get_latest_message_id(&state.db, &member).await {
+                Ok(Some(message_id)) => {
+                    publish_read(state, &member, username, message_id).await;
+                }
+                Ok(None) => {}
+                Err(err) => info!("{}", err.to_string()),
+            }
+            return Outcome::Continue;
+        }
+        ClientEvent::Typing | ClientEvent::Ping | ClientEvent::Resume { .. } => {
+            return Outcome::Continue
+        }
+    };
+
+    match published {
+        Ok(Some(event)) => {
+            if let Err(err) = state.broker.publish(Topic::Chat(chat_id), &event).await {
+                info!("{}", err.to_string());
+            }
+            activity::notify_members(state, &member).await;
+            Outcome::Continue
+        }
+        Ok(None) => Outcome::Reply(ServerEvent::Error {
+            error: "You can only change your own messages".to_owned(),
+        }),
+        Err(err) => {
+            info!("{}", err.to_string());
+            Outcome::Reply(ServerEvent::Error {
+                error: "Something went wrong, please try again".to_owned(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: i32) -> message::Model {
+        message::Model {
+            id,
+            text: format!("message {id}"),
+            user_id: 1,
+            chat_id: 1,
+            timestamp: id,
+            is_system: false,
+            edited_at: None,
+            deleted_at: None,
+            reply_to_id: None,
+            thread_root_id: None,
+        }
+    }
+
+    fn ids(messages: &[message::Model]) -> Vec<i32> {
+        messages.iter().map(|message| message.id).collect()
+    }
+
+    #[test]
+    fn replay_skips_messages_already_streamed() {
+        let mut backfill = Backfill::new();
+        assert!(backfill.live(3));
+        let replayed = backfill.replay(vec![message(1), message(2), message(3)]);
+        assert_eq!(ids(&replayed), [1, 2]);
+    }
+
+    #[test]
+    fn live_skips_messages_already_replayed() {
+        let mut backfill = Backfill::new();
+        backfill.replay(vec![message(1), message(2)]);
+        assert!(!backfill.live(2));
+        assert!(backfill.live(3));
+        // Replayed ids older than a live message can't come by anymore.
+        assert!(backfill.replayed.is_empty());
+    }
+
+    #[test]
+    fn only_the_first_resume_replays() {
+        let mut backfill = Backfill::new();
+        assert_eq!(ids(&backfill.replay(vec![message(1)])), [1]);
+        assert!(backfill.replay(vec![message(1), message(2)]).is_empty());
+    }
+
+    #[test]
+    fn an_overdue_resume_replays_nothing() {
+        let mut backfill = Backfill::new();
+        backfill.opened = Instant::now() - RESUME_TIMEOUT;
+        assert!(backfill.live(1));
+        assert!(backfill.streamed.is_none());
+        assert!(backfill.replay(vec![message(1), message(2)]).is_empty());
+    }
 }
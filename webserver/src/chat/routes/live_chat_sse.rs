@@ -0,0 +1,218 @@
+use crate::chat::broker::Topic;
+use crate::chat::database::{ChatDatabase, ChatMember};
+use crate::chat::events::ServerEvent;
+use crate::chat::hub::Subscription;
+use crate::chat::receipts::SeenBy;
+use crate::chat::routes::chat_page::forbidden;
+use crate::chat::routes::live_chat::{missed_messages, publish_read, MessageList};
+use crate::user::current_user::CurrentUser;
+use crate::user::database::UserDatabase;
+use askama::Template;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use axum::{debug_handler, response::IntoResponse};
+use futures::stream::{self, StreamExt};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub struct LiveChatSseRequest {
+    chat_id: i32,
+    /// The newest message the page already shows, like the socket's resume event.
+    after: Option<i32>,
+}
+
+/// The live chat for clients that can't keep a WebSocket open. Streams the same
+/// fragments as `live_chat_websocket` to the htmx `sse` extension, while
+/// messages are sent with a regular POST to `/messages/send`.
+#[debug_handler]
+pub async fn live_chat_sse(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    headers: HeaderMap,
+    Query(request): Query<LiveChatSseRequest>,
+) -> Response {
+    let user_id = current_user.id;
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, user_id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let username = match UserDatabase::get_user_by_id(&state.db, user_id).await {
+        Ok(Some(user)) => user.username,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let seen_by = match (
+        ChatDatabase::get_members(&state.db, &member).await,
+        ChatDatabase::get_latest_message_id(&state.db, &member).await,
+    ) {
+        (Ok(members), Ok(latest_message_id)) => SeenBy::new(user_id, latest_message_id, members),
+        (Err(err), _) | (_, Err(err)) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    // Subscribe before reading the backlog, so no message falls in between.
+    let subscription = match state.broker.subscribe(Topic::Chat(member.chat_id())).await {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // Browsers send the id of the last event they got when they reconnect.
+    let after = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok())
+        .or(request.after);
+
+    let mut live = LiveStream {
+        state,
+        member,
+        username,
+        subscription,
+        seen_by,
+        replayed: HashSet::new(),
+    };
+    let first = live.replay(after).await;
+
+    let events = stream::iter(first).chain(stream::unfold(live, |mut live| async move {
+        let event = live.next().await?;
+        Some((event, live))
+    }));
+    Sse::new(events.map(Ok::<_, Infallible>))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// The chat as streamed to one client, keeping track of the same state a socket does.
+struct LiveStream {
+    state: Arc<AppState>,
+    member: ChatMember,
+    username: String,
+    subscription: Subscription,
+    seen_by: SeenBy,
+    /// Messages sent with the replay, skipped when they come by live.
+    replayed: HashSet<i32>,
+}
+
+impl LiveStream {
+    /// The messages the client missed after `after`, together with who has seen them.
+    async fn replay(&mut self, after: Option<i32>) -> Option<Event> {
+        let messages = match after {
+            Some(after) => match missed_messages(&self.state, &self.member, after).await {
+                Ok(messages) => messages,
+                Err(err) => {
+                    info!("{}", err.to_string());
+                    vec![]
+                }
+            },
+            None => vec![],
+        };
+        let Some(last) = messages.last() else {
+            return self.render(None, String::new());
+        };
+        let (last_id, last_author_id) = (last.id, last.user_id);
+        self.replayed = messages.iter().map(|message| message.id).collect();
+        self.seen_by.message(last_id, last_author_id);
+        publish_read(&self.state, &self.member, &self.username, last_id).await;
+        let list =
+            MessageList::load(&self.state, &self.member, messages, self.member.user_id()).await;
+        match list.render() {
+            Ok(html) => self.render(Some(last_id), html),
+            Err(err) => {
+                info!("{}", err.to_string());
+                None
+            }
+        }
+    }
+
+    /// The next event to send, or `None` once the stream should end.
+    async fn next(&mut self) -> Option<Event> {
+        let user_id = self.member.user_id();
+        loop {
+            let event = self.subscription.recv().await?;
+            match &event {
+                ServerEvent::Message { message, .. } => {
+                    let replayed = self.replayed.remove(&message.id);
+                    // Older replayed messages were from before the subscription.
+                    self.replayed.retain(|id| *id > message.id);
+                    if replayed {
+                        continue;
+                    }
+                    // Stop streaming to users that were removed from the chat.
+                    if message.is_system
+                        && !matches!(
+                            ChatDatabase::authorize(&self.state.db, self.member.chat_id(), user_id)
+                                .await,
+                            Ok(Some(_))
+                        )
+                    {
+                        return None;
+                    }
+                    let (message_id, author_id) = (message.id, message.user_id);
+                    let html = match event.render(user_id) {
+                        Ok(html) => html,
+                        Err(err) => {
+                            info!("{}", err.to_string());
+                            continue;
+                        }
+                    };
+                    // The message reached an open chat, so it counts as read.
+                    self.seen_by.message(message_id, author_id);
+                    if author_id != user_id {
+                        publish_read(&self.state, &self.member, &self.username, message_id).await;
+                    }
+                    return self.render(Some(message_id), html);
+                }
+                ServerEvent::Read {
+                    user_id: reader,
+                    username,
+                    message_id,
+                } => {
+                    self.seen_by.read(*reader, Some(username), *message_id);
+                    return self.render(None, String::new());
+                }
+                // Typing notices need a socket to send them and expire them.
+                ServerEvent::Typing { .. } | ServerEvent::ChatChanged { .. } => continue,
+                _ => match event.render(user_id) {
+                    Ok(html) => return Some(Event::default().data(html)),
+                    Err(err) => info!("{}", err.to_string()),
+                },
+            }
+        }
+    }
+
+    /// Sends the fragment along with the receipts. Message events carry their id,
+    /// so a reconnecting client resumes after the last one it got.
+    fn render(&self, message_id: Option<i32>, html: String) -> Option<Event> {
+        let seen_by = match self.seen_by.render() {
+            Ok(seen_by) => seen_by,
+            Err(err) => {
+                info!("{}", err.to_string());
+                String::new()
+            }
+        };
+        let event = Event::default().data(html + &seen_by);
+        Some(match message_id {
+            Some(message_id) => event.id(message_id.to_string()),
+            None => event,
+        })
+    }
+}
@@ -0,0 +1,425 @@
+use crate::chat::activity;
+use crate::chat::broker::Topic;
+use crate::chat::database::{ChatDatabase, ChatMember, MemberSummary};
+use crate::chat::events::ServerEvent;
+use crate::chat::routes::chat_page::forbidden;
+use crate::chat::routes::get_chats::chats_template;
+use crate::entities::user_in_chat::ChatRole;
+use crate::user::current_user::CurrentUser;
+use crate::user::database::UserDatabase;
+use askama::Template;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{debug_handler, response::IntoResponse, Form};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{AppState, HtmlTemplate};
+
+#[derive(Template)]
+#[template(path = "chat-members.html")]
+pub struct ChatMembersTemplate {
+    chat_id: i32,
+    rows: Vec<MemberRow>,
+    can_add: bool,
+    error: Option<String>,
+}
+
+/// A member together with the actions the current user may take on them.
+pub struct MemberRow {
+    member: MemberSummary,
+    can_remove: bool,
+    can_transfer: bool,
+    /// The role the member can be switched to by the current user, if any.
+    toggled_role: Option<ChatRole>,
+}
+
+fn can_remove(actor: &ChatMember, target: &MemberSummary) -> bool {
+    actor.user_id() != target.user_id
+        && match actor.role() {
+            ChatRole::Owner => true,
+            ChatRole::Admin => target.role == ChatRole::Member,
+            ChatRole::Member => false,
+        }
+}
+
+fn toggled_role(actor: &ChatMember, target: &MemberSummary) -> Option<ChatRole> {
+    match (actor.role(), target.role) {
+        (ChatRole::Owner, ChatRole::Admin) => Some(ChatRole::Member),
+        (ChatRole::Owner, ChatRole::Member) => Some(ChatRole::Admin),
+        _ => None,
+    }
+}
+
+/// Authorizes the current user and makes sure the chat is a group, since
+/// direct messages always have exactly their two participants.
+async fn authorize_group(
+    state: &AppState,
+    chat_id: i32,
+    user_id: i32,
+) -> Result<ChatMember, Response> {
+    let member = match ChatDatabase::authorize(&state.db, chat_id, user_id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return Err(forbidden()),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+
+    match ChatDatabase::get_chat(&state.db, &member).await {
+        Ok(chat) if chat.chat.direct_key.is_none() => Ok(member),
+        Ok(_) => Err((
+            StatusCode::BAD_REQUEST,
+            "Direct chats have no members to manage",
+        )
+            .into_response()),
+        Err(err) => {
+            error!("{}", err.to_string());
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+async fn members_template(
+    state: &AppState,
+    member: &ChatMember,
+    error: Option<String>,
+) -> Response {
+    let members = match ChatDatabase::get_members(&state.db, member).await {
+        Ok(members) => members,
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let rows = members
+        .into_iter()
+        .map(|target| MemberRow {
+            can_remove: can_remove(member, &target),
+            can_transfer: member.role() == ChatRole::Owner && target.user_id != member.user_id(),
+            toggled_role: toggled_role(member, &target),
+            member: target,
+        })
+        .collect();
+
+    HtmlTemplate(ChatMembersTemplate {
+        chat_id: member.chat_id(),
+        rows,
+        can_add: member.role() != ChatRole::Member,
+        error,
+    })
+    .into_response()
+}
+
+/// Records a system message for the membership change and pushes it to the chat's
+/// channel and the sidebars of its members.
+async fn announce(state: &AppState, member: &ChatMember, text: String) {
+    match ChatDatabase::add_system_message(&state.db, member, text).await {
+        Ok(message) => {
+            let event = ServerEvent::Message {
+                message,
+                reply_to: None,
+                attachments: vec![],
+            };
+            if let Err(err) = state
+                .broker
+                .publish(Topic::Chat(member.chat_id()), &event)
+                .await
+            {
+                error!("{}", err.to_string());
+            }
+            activity::notify_members(state, member).await;
+        }
+        Err(err) => error!("{}", err.to_string()),
+    }
+}
+
+async fn username(state: &AppState, user_id: i32) -> String {
+    match UserDatabase::get_user_by_id(&state.db, user_id).await {
+        Ok(Some(user)) => user.username,
+        _ => format!("user {user_id}"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GetMembersRequest {
+    chat_id: i32,
+}
+
+#[debug_handler]
+pub async fn get_members(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<GetMembersRequest>,
+) -> Response {
+    match authorize_group(&state, request.chat_id, current_user.id).await {
+        Ok(member) => members_template(&state, &member, None).await,
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AddMembersRequest {
+    chat_id: i32,
+    /// Comma separated usernames.
+    usernames: String,
+}
+
+#[debug_handler]
+pub async fn add_members(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<AddMembersRequest>,
+) -> Response {
+    let member = match authorize_group(&state, request.chat_id, current_user.id).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+    if member.role() == ChatRole::Member {
+        return forbidden();
+    }
+
+    let usernames: Vec<String> = request
+        .usernames
+        .split(',')
+        .map(|username| username.trim().to_owned())
+        .filter(|username| !username.is_empty())
+        .collect();
+    let users = match UserDatabase::get_users_by_usernames(&state.db, usernames.clone()).await {
+        Ok(users) => users,
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let missing: Vec<&str> = usernames
+        .iter()
+        .filter(|username| !users.iter().any(|user| &user.username == *username))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        let error = format!("No users named {}", missing.join(", "));
+        return members_template(&state, &member, Some(error)).await;
+    }
+
+    let user_ids = users.iter().map(|user| user.id).collect();
+    let added = match ChatDatabase::add_members(&state.db, &member, user_ids).await {
+        Ok(added) => added,
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if !added.is_empty() {
+        let added_names: Vec<&str> = users
+            .iter()
+            .filter(|user| added.contains(&user.id))
+            .map(|user| user.username.as_str())
+            .collect();
+        let text = format!(
+            "{} added {}",
+            username(&state, member.user_id()).await,
+            added_names.join(", ")
+        );
+        announce(&state, &member, text).await;
+    }
+
+    members_template(&state, &member, None).await
+}
+
+#[derive(Deserialize)]
+pub struct MemberRequest {
+    chat_id: i32,
+    user_id: i32,
+}
+
+#[debug_handler]
+pub async fn remove_member(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<MemberRequest>,
+) -> Response {
+    let member = match authorize_group(&state, request.chat_id, current_user.id).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+    let target = match target_member(&state, &member, request.user_id).await {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+    if !can_remove(&member, &target) {
+        return forbidden();
+    }
+
+    if let Err(err) = ChatDatabase::remove_member(&state.db, &member, target.user_id).await {
+        error!("{}", err.to_string());
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let text = format!(
+        "{} removed {}",
+        username(&state, member.user_id()).await,
+        target.username
+    );
+    announce(&state, &member, text).await;
+    activity::notify_users(&state, member.chat_id(), &[target.user_id]).await;
+
+    members_template(&state, &member, None).await
+}
+
+#[derive(Deserialize)]
+pub struct SetRoleRequest {
+    chat_id: i32,
+    user_id: i32,
+    role: ChatRole,
+}
+
+#[debug_handler]
+pub async fn set_member_role(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<SetRoleRequest>,
+) -> Response {
+    let member = match authorize_group(&state, request.chat_id, current_user.id).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+    let target = match target_member(&state, &member, request.user_id).await {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+    if toggled_role(&member, &target) != Some(request.role) {
+        return forbidden();
+    }
+
+    if let Err(err) =
+        ChatDatabase::set_member_role(&state.db, &member, target.user_id, request.role).await
+    {
+        error!("{}", err.to_string());
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let text = format!(
+        "{} made {} {}",
+        username(&state, member.user_id()).await,
+        target.username,
+        match request.role {
+            ChatRole::Admin => "an admin",
+            _ => "a member",
+        }
+    );
+    announce(&state, &member, text).await;
+
+    members_template(&state, &member, None).await
+}
+
+#[debug_handler]
+pub async fn transfer_ownership(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<MemberRequest>,
+) -> Response {
+    let member = match authorize_group(&state, request.chat_id, current_user.id).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+    if member.role() != ChatRole::Owner {
+        return forbidden();
+    }
+    let target = match target_member(&state, &member, request.user_id).await {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+    if target.user_id == member.user_id() {
+        return members_template(&state, &member, None).await;
+    }
+
+    if let Err(err) = ChatDatabase::transfer_ownership(&state.db, &member, target.user_id).await {
+        error!("{}", err.to_string());
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let text = format!(
+        "{} transferred ownership to {}",
+        username(&state, member.user_id()).await,
+        target.username
+    );
+    announce(&state, &member, text).await;
+
+    // Re-authorize so the panel reflects the demotion to admin.
+    match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => members_template(&state, &member, None).await,
+        Ok(None) => forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LeaveChatRequest {
+    chat_id: i32,
+}
+
+#[debug_handler]
+pub async fn leave_chat(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<LeaveChatRequest>,
+) -> Response {
+    let member = match authorize_group(&state, request.chat_id, current_user.id).await {
+        Ok(member) => member,
+        Err(response) => return response,
+    };
+
+    if member.role() == ChatRole::Owner {
+        match ChatDatabase::get_members(&state.db, &member).await {
+            Ok(members) if members.len() > 1 => {
+                let error = "Transfer ownership before leaving the chat".to_owned();
+                return members_template(&state, &member, Some(error)).await;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                error!("{}", err.to_string());
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    if let Err(err) = ChatDatabase::remove_member(&state.db, &member, member.user_id()).await {
+        error!("{}", err.to_string());
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let text = format!("{} left", username(&state, member.user_id()).await);
+    announce(&state, &member, text).await;
+    activity::notify_users(&state, member.chat_id(), &[member.user_id()]).await;
+
+    chats_template(&state, current_user.id, None, None)
+        .await
+        .into_response()
+}
+
+async fn target_member(
+    state: &AppState,
+    member: &ChatMember,
+    user_id: i32,
+) -> Result<MemberSummary, Response> {
+    match ChatDatabase::get_members(&state.db, member).await {
+        Ok(members) => members
+            .into_iter()
+            .find(|target| target.user_id == user_id)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Not a member of this chat").into_response()),
+        Err(err) => {
+            error!("{}", err.to_string());
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::chat::activity;
+use crate::chat::broker::Topic;
+use crate::chat::database::{ChatDatabase, ChatMember, Reactions};
+use crate::chat::events::{self, ServerEvent};
+use crate::chat::routes::chat_page::forbidden;
+use crate::chat::routes::live_chat::{edit_event, MessageUpdate};
+use crate::user::current_user::CurrentUser;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{debug_handler, response::IntoResponse, Form};
+use sea_orm::DbErr;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{AppState, HtmlTemplate};
+
+#[derive(Deserialize)]
+pub struct MessageActionRequest {
+    chat_id: i32,
+    message_id: i32,
+}
+
+/// Renders the changed message for the author and pushes it to everyone else
+/// viewing the chat, where it replaces the existing element.
+async fn respond_and_publish(
+    state: &AppState,
+    member: &ChatMember,
+    event: ServerEvent,
+) -> Response {
+    if let Err(err) = state
+        .broker
+        .publish(Topic::Chat(member.chat_id()), &event)
+        .await
+    {
+        error!("{}", err.to_string());
+    }
+    activity::notify_members(state, member).await;
+
+    let update = match event {
+        ServerEvent::Edit {
+            message,
+            reply_to,
+            reactions,
+            attachments,
+        } => MessageUpdate {
+            reactions: Reactions::from((message.id, reactions)),
+            attachments: HashMap::from([(message.id, attachments)]),
+            messages: vec![message],
+            previews: events::previews(reply_to),
+            user_id: member.user_id(),
+            oob: false,
+        },
+        ServerEvent::Delete { message } => MessageUpdate {
+            messages: vec![message],
+            previews: HashMap::new(),
+            reactions: Reactions::default(),
+            attachments: HashMap::new(),
+            user_id: member.user_id(),
+            oob: false,
+        },
+        _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    HtmlTemplate(update).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SendMessageRequest {
+    chat_id: i32,
+    message: String,
+    /// Set when replying from the thread panel.
+    reply_to_id: Option<i32>,
+}
+
+/// Sends a message without a socket, for clients on the SSE stream and replies
+/// from the thread panel. The message reaches the sender over the chat stream
+/// like everyone else.
+#[debug_handler]
+pub async fn send_message(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<SendMessageRequest>,
+) -> Response {
+    let text = request.message.trim().to_owned();
+    if text.is_empty() {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let message =
+        match ChatDatabase::add_message(&state.db, &member, text, request.reply_to_id).await {
+            Ok(message) => message,
+            // Most likely a reply to a message that is not in the chat or was deleted.
+            Err(DbErr::RecordNotFound(_)) => return StatusCode::NOT_FOUND.into_response(),
+            Err(err) => {
+                error!("{}", err.to_string());
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+    let reply_to = match ChatDatabase::get_reply_preview(&state.db, &member, &message).await {
+        Ok(reply_to) => reply_to,
+        Err(err) => {
+            error!("{}", err.to_string());
+            None
+        }
+    };
+    let event = ServerEvent::Message {
+        message,
+        reply_to,
+        attachments: vec![],
+    };
+    if let Err(err) = state
+        .broker
+        .publish(Topic::Chat(member.chat_id()), &event)
+        .await
+    {
+        error!("{}", err.to_string());
+    }
+    activity::notify_members(&state, &member).await;
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Deserialize)]
+pub struct EditMessageRequest {
+    chat_id: i32,
+    message_id: i32,
+    text: String,
+}
+
+#[debug_handler]
+pub async fn edit_message(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<EditMessageRequest>,
+) -> Response {
+    let text = request.text.trim().to_owned();
+    if text.is_empty() {
+        return (StatusCode::BAD_REQUEST, "A message can't be empty").into_response();
+    }
+
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match ChatDatabase::edit_message(&state.db, &member, request.message_id, text).await {
+        Ok(Some(message)) => match edit_event(&state, &member, message).await {
+            Ok(event) => respond_and_publish(&state, &member, event).await,
+            Err(err) => {
+                error!("{}", err.to_string());
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        },
+        Ok(None) => forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn delete_message(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<MessageActionRequest>,
+) -> Response {
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match ChatDatabase::delete_message(&state.db, &member, request.message_id).await {
+        Ok(Some(message)) => {
+            respond_and_publish(&state, &member, ServerEvent::delete(message)).await
+        }
+        Ok(None) => forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -1,3 +1,12 @@
+pub mod attachments;
 pub mod chat_page;
+pub mod create_chat;
 pub mod get_chats;
 pub mod live_chat;
+pub mod live_chat_sse;
+pub mod members;
+pub mod messages;
+pub mod reactions;
+pub mod search;
+pub mod threads;
+pub mod user_events;
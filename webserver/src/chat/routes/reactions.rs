@@ -0,0 +1,94 @@
+use crate::chat::broker::Topic;
+use crate::chat::database::{ChatDatabase, Reactions};
+use crate::chat::events::ServerEvent;
+use crate::chat::routes::chat_page::forbidden;
+use crate::user::current_user::CurrentUser;
+use askama::Template;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{debug_handler, response::IntoResponse, Form};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{AppState, HtmlTemplate};
+
+/// The emoji offered in the picker, and the only ones accepted.
+pub const REACTION_EMOJI: [&str; 6] = ["👍", "❤️", "😂", "😮", "😢", "🎉"];
+
+/// The counts under a message. Pushed out-of-band to everyone in the chat when
+/// someone reacts, which is why it renders apart from the message.
+#[derive(Template)]
+#[template(path = "reactions.html")]
+pub struct ReactionBar {
+    pub chat_id: i32,
+    pub message_id: i32,
+    pub reactions: Reactions,
+    pub user_id: i32,
+    pub reactions_oob: bool,
+}
+
+#[derive(Deserialize)]
+pub struct ReactRequest {
+    chat_id: i32,
+    message_id: i32,
+    emoji: String,
+}
+
+/// Adds the reaction, or takes it back if the user already reacted with that emoji.
+#[debug_handler]
+pub async fn toggle_reaction(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<ReactRequest>,
+) -> Response {
+    if !REACTION_EMOJI.contains(&request.emoji.as_str()) {
+        return (StatusCode::BAD_REQUEST, "Unknown reaction").into_response();
+    }
+
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let reactions =
+        match ChatDatabase::toggle_reaction(&state.db, &member, request.message_id, request.emoji)
+            .await
+        {
+            Ok(Some(reactions)) => reactions,
+            Ok(None) => {
+                return (StatusCode::NOT_FOUND, "No such message in this chat").into_response()
+            }
+            Err(err) => {
+                error!("{}", err.to_string());
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+
+    let event = ServerEvent::Reactions {
+        chat_id: member.chat_id(),
+        message_id: request.message_id,
+        reactions: reactions.clone(),
+    };
+    if let Err(err) = state
+        .broker
+        .publish(Topic::Chat(member.chat_id()), &event)
+        .await
+    {
+        error!("{}", err.to_string());
+    }
+
+    HtmlTemplate(ReactionBar {
+        chat_id: member.chat_id(),
+        message_id: request.message_id,
+        reactions: Reactions::from((request.message_id, reactions)),
+        user_id: current_user.id,
+        reactions_oob: false,
+    })
+    .into_response()
+}
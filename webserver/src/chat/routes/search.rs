@@ -0,0 +1,86 @@
+use crate::chat::database::{ChatDatabase, SearchHit};
+use crate::user::current_user::CurrentUser;
+use askama::Template;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{debug_handler, response::IntoResponse, Form};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{AppState, HtmlTemplate};
+
+#[derive(Template)]
+#[template(path = "search-results.html")]
+pub struct SearchResults {
+    query: String,
+    hits: Vec<SearchHit>,
+}
+
+/// Most hits shown for a search, refining the query beats scrolling.
+const SEARCH_LIMIT: u64 = 30;
+
+#[derive(Deserialize)]
+pub struct SearchRequest {
+    q: String,
+}
+
+#[debug_handler]
+pub async fn search_messages(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<SearchRequest>,
+) -> Response {
+    let query = request.q.trim().to_owned();
+    if query.is_empty() {
+        return HtmlTemplate(SearchResults {
+            query,
+            hits: vec![],
+        })
+        .into_response();
+    }
+
+    match ChatDatabase::search_messages(&state.db, current_user.id, &query, SEARCH_LIMIT).await {
+        Ok(hits) => HtmlTemplate(SearchResults { query, hits }).into_response(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::database::Snippet;
+
+    #[test]
+    fn hits_escape_the_message_around_the_highlights() {
+        let hit = SearchHit {
+            message_id: 1,
+            chat_id: 1,
+            chat_name: "general".to_owned(),
+            username: "alice".to_owned(),
+            timestamp: 0,
+            snippets: vec![
+                Snippet {
+                    text: "<b>".to_owned(),
+                    matched: false,
+                },
+                Snippet {
+                    text: "<script>".to_owned(),
+                    matched: true,
+                },
+            ],
+        };
+        let html = SearchResults {
+            query: "script".to_owned(),
+            hits: vec![hit],
+        }
+        .render()
+        .unwrap();
+        assert!(html.contains("&lt;b&gt;<mark>&lt;script&gt;</mark>"));
+        assert!(!html.contains("<script>"));
+    }
+}
@@ -0,0 +1,64 @@
+use crate::chat::database::ChatDatabase;
+use crate::chat::routes::chat_page::forbidden;
+use crate::user::current_user::CurrentUser;
+use askama::Template;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{debug_handler, response::IntoResponse, Form};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{entities::message, AppState, HtmlTemplate};
+
+/// The side panel with a thread's root and its replies. New replies are added
+/// out-of-band by the chat stream as they arrive.
+#[derive(Template)]
+#[template(path = "thread-panel.html")]
+pub struct ThreadPanel {
+    chat_id: i32,
+    user_id: i32,
+    root: message::Model,
+    replies: Vec<message::Model>,
+    /// The message the form replies to, the root or one of the replies.
+    reply_to_id: i32,
+}
+
+#[derive(Deserialize)]
+pub struct ThreadRequest {
+    chat_id: i32,
+    message_id: i32,
+}
+
+#[debug_handler]
+pub async fn thread_panel(
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+    Form(request): Form<ThreadRequest>,
+) -> Response {
+    let member = match ChatDatabase::authorize(&state.db, request.chat_id, current_user.id).await {
+        Ok(Some(member)) => member,
+        Ok(None) => return forbidden(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match ChatDatabase::get_thread(&state.db, &member, request.message_id).await {
+        Ok(Some((root, replies))) => HtmlTemplate(ThreadPanel {
+            chat_id: request.chat_id,
+            user_id: current_user.id,
+            root,
+            replies,
+            reply_to_id: request.message_id,
+        })
+        .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "No such message in this chat").into_response(),
+        Err(err) => {
+            error!("{}", err.to_string());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
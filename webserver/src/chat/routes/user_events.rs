@@ -0,0 +1,103 @@
+use crate::chat::broker::Topic;
+use crate::chat::events::ServerEvent;
+use crate::chat::presence::{self, PresenceConnection, HEARTBEAT_INTERVAL};
+use crate::chat::routes::get_chats::ChatRowUpdate;
+use crate::user::current_user::CurrentUser;
+use askama::Template;
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{State, WebSocketUpgrade};
+use axum::{debug_handler, response::IntoResponse};
+use futures::{sink::SinkExt, stream::StreamExt};
+use std::sync::Arc;
+use tracing::info;
+
+use crate::AppState;
+
+#[debug_handler]
+pub async fn user_events_websocket(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    current_user: CurrentUser,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| user_events(socket, state, current_user.id))
+}
+
+/// Keeps the user's sidebar up to date with activity in every chat, not just
+/// the open one, and with who of their direct chat partners is online.
+async fn user_events(stream: WebSocket, state: Arc<AppState>, user_id: i32) {
+    let mut subscription = match state.broker.subscribe(Topic::User(user_id)).await {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            info!("{}", err.to_string());
+            return;
+        }
+    };
+    // The sidebar is open whenever the app is, so it counts towards being online.
+    let presence = match PresenceConnection::connect(state.presence.as_ref(), user_id).await {
+        Ok((presence, came_online)) => {
+            if came_online {
+                presence::announce(&state, user_id, true).await;
+            }
+            Some(presence)
+        }
+        Err(err) => {
+            info!("{}", err.to_string());
+            None
+        }
+    };
+    let (mut sender, mut receiver) = stream.split();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        let event = tokio::select! {
+            event = subscription.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+            _ = heartbeat.tick() => {
+                if let Some(presence) = &presence {
+                    if let Err(err) = presence.heartbeat(state.presence.as_ref()).await {
+                        info!("{}", err.to_string());
+                    }
+                }
+                continue;
+            },
+            // The browser never sends anything, this only notices the socket closing.
+            frame = receiver.next() => match frame {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        let html = match event {
+            ServerEvent::ChatChanged { chat_id } => {
+                match ChatRowUpdate::new(&state, chat_id, user_id).await {
+                    Ok(row) => row.render(),
+                    Err(err) => {
+                        info!("{}", err.to_string());
+                        continue;
+                    }
+                }
+            }
+            // Swaps the dot next to the direct chat with that user.
+            event @ ServerEvent::Presence { .. } => event.render(user_id),
+            _ => continue,
+        };
+        match html {
+            Ok(html) => {
+                if sender.send(Message::Text(html)).await.is_err() {
+                    break;
+                }
+            }
+            Err(err) => info!("{}", err.to_string()),
+        }
+    }
+
+    if let Some(presence) = presence {
+        match presence.disconnect(state.presence.as_ref()).await {
+            Ok(true) => presence::announce(&state, user_id, false).await,
+            Ok(false) => {}
+            Err(err) => info!("{}", err.to_string()),
+        }
+    }
+}
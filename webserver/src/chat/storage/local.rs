@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use async_trait::async_trait;
+use tokio::fs;
+
+use super::Storage;
+
+/// Keeps every file in one directory on the server's disk, named by its key.
+/// Only fits a single process or a directory shared between them.
+pub struct LocalStorage {
+    dir: PathBuf,
+    max_size: usize,
+}
+
+impl LocalStorage {
+    /// Creates `dir` if it doesn't exist yet.
+    pub async fn open(dir: impl AsRef<Path>, max_size: usize) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir).await?;
+        Ok(Self { dir, max_size })
+    }
+
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        // Keys are generated by the server, this only guards against mistakes.
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            bail!("Invalid storage key {key}");
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    async fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        if data.len() > self.max_size {
+            bail!(
+                "{} bytes is over the limit of {}",
+                data.len(),
+                self.max_size
+            );
+        }
+        let path = self.path(key)?;
+        // Write next to the file and move it in place, so a half written file is never read.
+        let partial = path.with_extension("partial");
+        fs::write(&partial, data).await?;
+        fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        Ok(fs::read(self.path(key)?).await?)
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        Ok(fs::remove_file(self.path(key)?).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage() -> LocalStorage {
+        LocalStorage {
+            dir: PathBuf::from("/srv/attachments"),
+            max_size: 16,
+        }
+    }
+
+    #[test]
+    fn keys_map_to_files_in_the_directory() {
+        let key = "0b5e7c3a-51d2-4f0e-9c1b-2a6f3d8e4b71";
+        assert_eq!(
+            storage().path(key).unwrap(),
+            Path::new("/srv/attachments").join(key)
+        );
+    }
+
+    #[test]
+    fn keys_cannot_leave_the_directory() {
+        for key in [
+            "",
+            "..",
+            "../etc/passwd",
+            "a/b",
+            "/etc/passwd",
+            "a.partial",
+            "a\0b",
+        ] {
+            assert!(storage().path(key).is_err(), "{key:?} was accepted");
+        }
+    }
+
+    #[tokio::test]
+    async fn files_over_the_limit_are_refused() {
+        let dir = std::env::temp_dir().join(format!("storage-test-{}", std::process::id()));
+        let storage = LocalStorage::open(&dir, 4).await.unwrap();
+        assert!(storage.put("small", b"1234").await.is_ok());
+        assert_eq!(storage.get("small").await.unwrap(), b"1234");
+        assert!(storage.put("large", b"12345").await.is_err());
+        storage.delete("small").await.unwrap();
+        fs::remove_dir(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+mod local;
+
+pub use local::LocalStorage;
+
+use async_trait::async_trait;
+
+/// Where attachment contents are kept, the database only has what describes them.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// The largest file accepted, in bytes.
+    fn max_size(&self) -> usize;
+
+    async fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()>;
+
+    async fn get(&self, key: &str) -> anyhow::Result<Vec<u8>>;
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+/// The type of a file judged by its contents, since the name and the type the
+/// browser sent are up to the uploader.
+pub fn sniff_content_type(data: &[u8]) -> String {
+    match infer::get(data) {
+        Some(kind) => kind.mime_type().to_owned(),
+        None if std::str::from_utf8(data).is_ok() => "text/plain".to_owned(),
+        None => "application/octet-stream".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types_come_from_the_contents() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(sniff_content_type(png), "image/png");
+        assert_eq!(sniff_content_type(b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(sniff_content_type("héllo".as_bytes()), "text/plain");
+        assert_eq!(
+            sniff_content_type(b"\xff\xfe\x00\x81"),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::{Duration, Instant};
+
+use askama::Template;
+
+/// How long a typing notice is shown without a newer one from the same user.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Template)]
+#[template(path = "typing.html")]
+pub struct TypingIndicator {
+    pub names: Vec<String>,
+}
+
+/// Users currently typing in a chat, as seen by one socket. Entries expire on
+/// their own so a client that disconnects mid-sentence doesn't linger.
+#[derive(Default)]
+pub struct TypingUsers {
+    users: Vec<(i32, String, Instant)>,
+}
+
+impl TypingUsers {
+    pub fn start(&mut self, user_id: i32, username: String) {
+        let expires_at = Instant::now() + TYPING_TIMEOUT;
+        match self.users.iter_mut().find(|(id, _, _)| *id == user_id) {
+            Some(entry) => entry.2 = expires_at,
+            None => self.users.push((user_id, username, expires_at)),
+        }
+    }
+
+    /// Returns whether the user was shown as typing.
+    pub fn stop(&mut self, user_id: i32) -> bool {
+        let before = self.users.len();
+        self.users.retain(|(id, _, _)| *id != user_id);
+        self.users.len() != before
+    }
+
+    /// Drops expired entries, returning whether anything changed.
+    pub fn expire(&mut self) -> bool {
+        let now = Instant::now();
+        let before = self.users.len();
+        self.users.retain(|(_, _, expires_at)| *expires_at > now);
+        self.users.len() != before
+    }
+
+    pub fn render(&self) -> askama::Result<String> {
+        TypingIndicator {
+            names: self
+                .users
+                .iter()
+                .map(|(_, username, _)| username.clone())
+                .collect(),
+        }
+        .render()
+    }
+}
@@ -9,12 +9,26 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     pub last_changed_timestamp: i32,
+    pub name: Option<String>,
+    #[sea_orm(unique)]
+    pub direct_key: Option<String>,
+    pub topic: Option<String>,
+    pub avatar_url: Option<String>,
+    pub owner_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::message::Entity")]
     Message,
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::OwnerId",
+        to = "super::user::Column::Id",
+        on_update = "NoAction",
+        on_delete = "NoAction"
+    )]
+    User,
     #[sea_orm(has_many = "super::user_in_chat::Entity")]
     UserInChat,
 }
@@ -25,6 +39,12 @@ impl Related<super::message::Entity> for Entity {
     }
 }
 
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
 impl Related<super::user_in_chat::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserInChat.def()
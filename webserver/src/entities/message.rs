@@ -12,10 +12,17 @@ pub struct Model {
     pub user_id: i32,
     pub chat_id: i32,
     pub timestamp: i32,
+    pub is_system: bool,
+    pub edited_at: Option<i32>,
+    pub deleted_at: Option<i32>,
+    pub reply_to_id: Option<i32>,
+    pub thread_root_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::attachment::Entity")]
+    Attachment,
     #[sea_orm(
         belongs_to = "super::chat::Entity",
         from = "Column::ChatId",
@@ -24,6 +31,8 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     Chat,
+    #[sea_orm(has_many = "super::message_reaction::Entity")]
+    MessageReaction,
     #[sea_orm(
         belongs_to = "super::user::Entity",
         from = "Column::UserId",
@@ -34,12 +43,24 @@ pub enum Relation {
     User,
 }
 
+impl Related<super::attachment::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Attachment.def()
+    }
+}
+
 impl Related<super::chat::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Chat.def()
     }
 }
 
+impl Related<super::message_reaction::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MessageReaction.def()
+    }
+}
+
 impl Related<super::user::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::User.def()
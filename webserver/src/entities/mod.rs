@@ -1,8 +1,9 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.10
 
-pub mod prelude;
-
+pub mod attachment;
 pub mod chat;
 pub mod message;
+pub mod message_reaction;
+pub mod session;
 pub mod user;
 pub mod user_in_chat;
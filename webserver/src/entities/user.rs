@@ -15,18 +15,42 @@ pub struct Model {
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::chat::Entity")]
+    Chat,
     #[sea_orm(has_many = "super::message::Entity")]
     Message,
+    #[sea_orm(has_many = "super::message_reaction::Entity")]
+    MessageReaction,
+    #[sea_orm(has_many = "super::session::Entity")]
+    Session,
     #[sea_orm(has_many = "super::user_in_chat::Entity")]
     UserInChat,
 }
 
+impl Related<super::chat::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Chat.def()
+    }
+}
+
 impl Related<super::message::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Message.def()
     }
 }
 
+impl Related<super::message_reaction::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MessageReaction.def()
+    }
+}
+
+impl Related<super::session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
 impl Related<super::user_in_chat::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::UserInChat.def()
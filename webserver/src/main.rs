@@ -4,6 +4,7 @@ mod user;
 
 use askama::Template;
 use axum::{
+    extract::DefaultBodyLimit,
     http::{header::CONTENT_TYPE, Method, StatusCode},
     response::{Html, IntoResponse, Response},
     routing::{get, post},
@@ -22,8 +23,30 @@ use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::{
-    chat::routes::{chat_page::chat_page, get_chats::get_chats, live_chat::live_chat_websocket},
-    user::routes::create_user::create_user,
+    chat::broker::{Broker, MemoryBroker, RedisBroker, RedisStreamBroker},
+    chat::presence::PresenceTracker,
+    chat::routes::{
+        attachments::{download_attachment, upload_attachment},
+        chat_page::{chat_page, newer_messages, older_messages},
+        create_chat::create_chat,
+        get_chats::get_chats,
+        live_chat::live_chat_websocket,
+        live_chat_sse::live_chat_sse,
+        members::{
+            add_members, get_members, leave_chat, remove_member, set_member_role,
+            transfer_ownership,
+        },
+        messages::{delete_message, edit_message, send_message},
+        reactions::toggle_reaction,
+        search::search_messages,
+        threads::thread_panel,
+        user_events::user_events_websocket,
+    },
+    chat::storage::{LocalStorage, Storage},
+    user::{
+        routes::{create_user::create_user, login::login, logout::logout},
+        session::SessionKeys,
+    },
 };
 
 #[tokio::main]
@@ -42,7 +65,9 @@ async fn main() -> anyhow::Result<()> {
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set in .env file");
     let port = env::var("PORT").expect("PORT is not set in .env file");
     let host = env::var("HOST").expect("HOST is not set in .env file");
-    let redis_url = env::var("REDIS_URL").expect("REDIS_URL is not set in .env file");
+    // A single process can keep chat events in memory, several need Redis.
+    let broker_kind = env::var("BROKER").unwrap_or_else(|_| "redis".to_owned());
+    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET is not set in .env file");
 
     let mut opt = ConnectOptions::new(db_url);
     opt.max_connections(100)
@@ -53,8 +78,42 @@ async fn main() -> anyhow::Result<()> {
 
     let db: DatabaseConnection = Database::connect(opt).await?;
     Migrator::up(&db, None).await?;
-    let client = redis::Client::open(redis_url).expect("Failed starting redis");
-    let state = AppState { db, redis: client };
+    let (broker, presence): (Arc<dyn Broker>, Arc<dyn PresenceTracker>) = match broker_kind.as_str()
+    {
+        "memory" => {
+            let broker = Arc::new(MemoryBroker::default());
+            (broker.clone(), broker)
+        }
+        "redis" => {
+            let redis_url = env::var("REDIS_URL").expect("REDIS_URL is not set in .env file");
+            let broker = RedisBroker::connect(&redis_url).await?;
+            let presence = Arc::new(broker.presence());
+            (Arc::new(broker), presence)
+        }
+        // Keeps events in a capped stream per chat instead of fire-and-forget pub/sub.
+        "redis-streams" => {
+            let redis_url = env::var("REDIS_URL").expect("REDIS_URL is not set in .env file");
+            let max_len = env::var("STREAM_MAXLEN")
+                .map(|max_len| max_len.parse().expect("STREAM_MAXLEN is not a number"))
+                .unwrap_or(1000);
+            let broker = RedisStreamBroker::connect(&redis_url, max_len).await?;
+            let presence = Arc::new(broker.presence());
+            (Arc::new(broker), presence)
+        }
+        other => panic!("Unknown BROKER {other}, expected redis, redis-streams or memory"),
+    };
+    let storage_dir = env::var("STORAGE_DIR").unwrap_or_else(|_| "uploads".to_owned());
+    let max_upload_size = env::var("MAX_UPLOAD_SIZE")
+        .map(|size| size.parse().expect("MAX_UPLOAD_SIZE is not a number"))
+        .unwrap_or(10 * 1024 * 1024);
+    let storage = Arc::new(LocalStorage::open(storage_dir, max_upload_size).await?);
+    let state = AppState {
+        db,
+        broker,
+        presence,
+        storage,
+        session_keys: SessionKeys::new(jwt_secret.as_bytes()),
+    };
 
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST])
@@ -66,9 +125,34 @@ async fn main() -> anyhow::Result<()> {
     let api_router = Router::new()
         .route("/hello", get(hello_from_the_server))
         .route("/live_chat", get(live_chat_websocket))
+        .route("/live_chat/sse", get(live_chat_sse))
+        .route("/user_events", get(user_events_websocket))
         .route("/chat_page", get(chat_page))
         .route("/get_chats", get(get_chats))
+        .route("/chats", post(create_chat))
+        .route("/chats/members", get(get_members))
+        .route("/chats/members/add", post(add_members))
+        .route("/chats/members/remove", post(remove_member))
+        .route("/chats/members/role", post(set_member_role))
+        .route("/chats/members/transfer", post(transfer_ownership))
+        .route("/chats/leave", post(leave_chat))
+        .route("/messages/send", post(send_message))
+        .route("/messages/edit", post(edit_message))
+        .route("/messages/delete", post(delete_message))
+        .route("/messages/react", post(toggle_reaction))
+        .route("/messages/older", get(older_messages))
+        .route("/messages/newer", get(newer_messages))
+        .route("/messages/search", get(search_messages))
+        .route("/threads", get(thread_panel))
+        .route("/attachments", get(download_attachment))
+        .route(
+            "/attachments/upload",
+            // The handler enforces the file's own limit, this leaves room for the other fields.
+            post(upload_attachment).layer(DefaultBodyLimit::max(max_upload_size + 64 * 1024)),
+        )
         .route("/create_user", post(create_user))
+        .route("/login", post(login))
+        .route("/logout", post(logout))
         .with_state(Arc::new(state));
 
     let app = Router::new()
@@ -97,7 +181,10 @@ async fn hello_from_the_server() -> &'static str {
 
 pub struct AppState {
     db: DatabaseConnection,
-    redis: redis::Client,
+    broker: Arc<dyn Broker>,
+    presence: Arc<dyn PresenceTracker>,
+    storage: Arc<dyn Storage>,
+    session_keys: SessionKeys,
 }
 
 async fn another_page() -> impl IntoResponse {
@@ -111,10 +198,12 @@ struct AnotherPageTemplate;
 
 #[derive(Template)]
 #[template(path = "hello.html")]
-pub struct HelloTemplate;
+pub struct HelloTemplate {
+    error: Option<String>,
+}
 
 async fn hello() -> impl IntoResponse {
-    let hello = HelloTemplate {};
+    let hello = HelloTemplate { error: None };
 
     HtmlTemplate(hello)
 }
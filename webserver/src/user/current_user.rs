@@ -0,0 +1,68 @@
+use crate::user::database::UserDatabase;
+use crate::user::routes::login::LoginForm;
+use crate::user::session::SESSION_COOKIE;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum_extra::extract::cookie::CookieJar;
+use axum_extra::headers::authorization::Bearer;
+use axum_extra::headers::Authorization;
+use axum_extra::TypedHeader;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{AppState, HtmlTemplate};
+
+/// The logged in user, resolved from the `session` cookie or an
+/// `Authorization: Bearer` header. Handlers should take this instead of
+/// trusting a user id sent by the browser.
+pub struct CurrentUser {
+    pub id: i32,
+    pub session_id: i32,
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for CurrentUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let bearer = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .map(|TypedHeader(Authorization(bearer))| bearer.token().to_owned());
+        let token = match bearer {
+            Some(token) => Some(token),
+            None => CookieJar::from_headers(&parts.headers)
+                .get(SESSION_COOKIE)
+                .map(|cookie| cookie.value().to_owned()),
+        };
+
+        let claims = token
+            .and_then(|token| state.session_keys.verify(&token).ok())
+            .ok_or_else(unauthorized)?;
+
+        match UserDatabase::get_active_session(&state.db, claims.sid).await {
+            Ok(Some(session)) if session.user_id == claims.sub => Ok(CurrentUser {
+                id: session.user_id,
+                session_id: session.id,
+            }),
+            Ok(_) => Err(unauthorized()),
+            Err(err) => {
+                error!("{}", err.to_string());
+                Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            }
+        }
+    }
+}
+
+fn unauthorized() -> Response {
+    let template = LoginForm {
+        error: Some("Please log in".to_owned()),
+    };
+    (StatusCode::UNAUTHORIZED, HtmlTemplate(template)).into_response()
+}
@@ -1,6 +1,8 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use sea_orm::*;
 
-use crate::entities::user;
+use crate::entities::{session, user};
 
 pub struct UserDatabase;
 
@@ -17,15 +19,70 @@ impl UserDatabase {
         Ok(())
     }
 
-    pub async fn get_user_for_login(
+    pub async fn get_user_by_username(
         db: &DbConn,
         username: String,
-        password: String,
     ) -> Result<Option<user::Model>, DbErr> {
         user::Entity::find()
             .filter(user::Column::Username.eq(username))
-            .filter(user::Column::PasswordHash.eq(password))
             .one(db)
             .await
     }
+
+    pub async fn get_user_by_id(db: &DbConn, user_id: i32) -> Result<Option<user::Model>, DbErr> {
+        user::Entity::find_by_id(user_id).one(db).await
+    }
+
+    pub async fn get_users_by_usernames(
+        db: &DbConn,
+        usernames: Vec<String>,
+    ) -> Result<Vec<user::Model>, DbErr> {
+        user::Entity::find()
+            .filter(user::Column::Username.is_in(usernames))
+            .all(db)
+            .await
+    }
+
+    pub async fn create_session(
+        db: &DbConn,
+        user_id: i32,
+        duration_secs: i32,
+    ) -> Result<session::Model, DbErr> {
+        let seconds_since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards!")
+            .as_secs() as i32;
+
+        let model = session::ActiveModel {
+            user_id: Set(user_id),
+            created_at: Set(seconds_since_epoch),
+            expires_at: Set(seconds_since_epoch + duration_secs),
+            ..Default::default()
+        }
+        .save(db)
+        .await?;
+
+        session::Model::try_from(model)
+    }
+
+    /// Returns the session only if it has not been revoked and is not expired.
+    pub async fn get_active_session(
+        db: &DbConn,
+        session_id: i32,
+    ) -> Result<Option<session::Model>, DbErr> {
+        let seconds_since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards!")
+            .as_secs() as i32;
+
+        session::Entity::find_by_id(session_id)
+            .filter(session::Column::ExpiresAt.gt(seconds_since_epoch))
+            .one(db)
+            .await
+    }
+
+    pub async fn delete_session(db: &DbConn, session_id: i32) -> Result<(), DbErr> {
+        session::Entity::delete_by_id(session_id).exec(db).await?;
+        Ok(())
+    }
 }
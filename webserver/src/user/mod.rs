@@ -1,2 +1,4 @@
-mod database;
+pub mod current_user;
+pub mod database;
 pub mod routes;
+pub mod session;
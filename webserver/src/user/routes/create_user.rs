@@ -6,7 +6,7 @@ use bcrypt::hash;
 use serde::Deserialize;
 use std::sync::Arc;
 
-use crate::AppState;
+use crate::{AppState, HtmlTemplate};
 
 #[derive(Template)]
 #[template(path = "success.html")]
@@ -26,7 +26,9 @@ pub async fn create_user(
     let CreateUserRequest { username, password } = message;
     let password_hash = hash(password, bcrypt::DEFAULT_COST).unwrap();
 
-    let _res = UserDatabase::create_user(&state.db, username, password_hash)
+    UserDatabase::create_user(&state.db, username, password_hash)
         .await
         .unwrap();
+
+    HtmlTemplate(SuccessMessage {})
 }
@@ -0,0 +1,82 @@
+use crate::user::database::UserDatabase;
+use crate::user::session::{Claims, SESSION_COOKIE, SESSION_DURATION_SECS};
+use askama::Template;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{debug_handler, response::IntoResponse, Form};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use bcrypt::verify;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{AppState, HtmlTemplate};
+
+#[derive(Template)]
+#[template(path = "login-form.html")]
+pub struct LoginForm {
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "logged-in.html")]
+pub struct LoggedIn;
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[debug_handler]
+pub async fn login(
+    State(state): State<Arc<AppState>>,
+    jar: CookieJar,
+    Form(request): Form<LoginRequest>,
+) -> Response {
+    let LoginRequest { username, password } = request;
+
+    let user = match UserDatabase::get_user_by_username(&state.db, username).await {
+        Ok(Some(user)) if verify(password, &user.password_hash).unwrap_or(false) => user,
+        Ok(_) => {
+            let template = LoginForm {
+                error: Some("Invalid username or password".to_owned()),
+            };
+            return (StatusCode::UNAUTHORIZED, HtmlTemplate(template)).into_response();
+        }
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let session =
+        match UserDatabase::create_session(&state.db, user.id, SESSION_DURATION_SECS).await {
+            Ok(session) => session,
+            Err(err) => {
+                error!("{}", err.to_string());
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+
+    let claims = Claims {
+        sub: user.id,
+        sid: session.id,
+        exp: session.expires_at as usize,
+    };
+    let token = match state.session_keys.sign(&claims) {
+        Ok(token) => token,
+        Err(err) => {
+            error!("{}", err.to_string());
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let cookie = Cookie::build((SESSION_COOKIE, token))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax);
+
+    (jar.add(cookie), HtmlTemplate(LoggedIn)).into_response()
+}
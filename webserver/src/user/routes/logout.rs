@@ -0,0 +1,29 @@
+use crate::user::current_user::CurrentUser;
+use crate::user::database::UserDatabase;
+use crate::user::routes::login::LoginForm;
+use crate::user::session::SESSION_COOKIE;
+use axum::extract::State;
+use axum::{debug_handler, response::IntoResponse};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{AppState, HtmlTemplate};
+
+#[debug_handler]
+pub async fn logout(
+    State(state): State<Arc<AppState>>,
+    current_user: Option<CurrentUser>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    if let Some(current_user) = current_user {
+        if let Err(err) = UserDatabase::delete_session(&state.db, current_user.session_id).await {
+            error!("{}", err.to_string());
+        }
+    }
+
+    (
+        jar.remove(Cookie::build(SESSION_COOKIE).path("/")),
+        HtmlTemplate(LoginForm { error: None }),
+    )
+}
@@ -1 +1,3 @@
 pub mod create_user;
+pub mod login;
+pub mod logout;